@@ -66,6 +66,45 @@ function SocketWrapper:on(event, callback)
     return { event, callback }
 end
 
+-- Shows why the last build failed
+local Overlay = Instance.new("ScreenGui")
+Overlay.Name = "LuaJoinBuildError"
+Overlay.DisplayOrder = 1e6
+Overlay.IgnoreGuiInset = true
+Overlay.Enabled = false
+Overlay.Parent = gethui and gethui() or game:GetService("CoreGui")
+
+local OverlayText = Instance.new("TextLabel")
+OverlayText.Size = UDim2.fromScale(1, 1)
+OverlayText.BackgroundColor3 = Color3.fromRGB(30, 0, 0)
+OverlayText.BackgroundTransparency = 0.15
+OverlayText.TextColor3 = Color3.fromRGB(255, 200, 200)
+OverlayText.Font = Enum.Font.Code
+OverlayText.TextSize = 16
+OverlayText.TextWrapped = true
+OverlayText.TextXAlignment = Enum.TextXAlignment.Left
+OverlayText.TextYAlignment = Enum.TextYAlignment.Top
+OverlayText.Parent = Overlay
+
+-- Formats a diagnostic like the terminal does, with its code frame
+local function formatDiagnostic(diagnostic)
+    local location = ""
+    if diagnostic.file and diagnostic.line then
+        location = diagnostic.file .. ":" .. diagnostic.line .. ": "
+    elseif diagnostic.file then
+        location = diagnostic.file .. ": "
+    elseif diagnostic.module then
+        location = "'" .. diagnostic.module .. "': "
+    end
+
+    local text = location .. diagnostic.severity .. "[" .. diagnostic.code .. "]: " .. diagnostic.message
+    if diagnostic.frame then
+        text = text .. "\n" .. diagnostic.frame
+    end
+
+    return text
+end
+
 while task.wait(WEBSOCKET_RETRY_DELAY) do
     local success, socket = pcall(function()
         return SocketWrapper.new("ws://192.168.1.171:1338")
@@ -81,6 +120,21 @@ while task.wait(WEBSOCKET_RETRY_DELAY) do
         loadstring(str)()
     end)
 
+    -- Listen to the builds, the bundle isn't executed until a failed one is fixed
+    socket:on("build_error", function(content)
+        local lines = { "Build failed", "" }
+        for _, diagnostic in pairs(HttpService:JSONDecode(content).diagnostics) do
+            table.insert(lines, formatDiagnostic(diagnostic))
+        end
+
+        OverlayText.Text = table.concat(lines, "\n")
+        Overlay.Enabled = true
+    end)
+
+    socket:on("build_ok", function()
+        Overlay.Enabled = false
+    end)
+
     -- Listen to errors
     for _, v in pairs(getconnections(ScriptContext.ErrorDetailed)) do
         v:Disable()
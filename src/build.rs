@@ -3,6 +3,14 @@ use full_moon::{
     visitors::VisitorMut,
 };
 
+/// Strips the whitespace and comments of some code
+pub fn minify(code: &str) -> Result<String, full_moon::Error> {
+    let ast = full_moon::parse(code)?;
+    let built_ast = BuildVisitor {}.visit_ast(ast);
+
+    Ok(full_moon::print(&built_ast))
+}
+
 pub struct BuildVisitor;
 
 impl VisitorMut for BuildVisitor {
@@ -23,31 +31,24 @@ impl VisitorMut for BuildVisitor {
                         characters: "".into(),
                     });
                 }
-
-                return token;
             }
 
-            return token;
+            token
         }));
 
         let trailing_trivia: Vec<Token> = Vec::from_iter(node.trailing_trivia().map(|token| {
             let token = token.clone();
 
             if let TokenType::Whitespace { characters } = token.token_type() {
-                if characters.contains("\n") || characters.contains("\r") {
-                    return Token::new(TokenType::Whitespace {
-                        characters: " ".into(),
-                    });
-                } else if !characters.is_empty() {
+                // Any whitespace (including newlines) collapses into a single space
+                if !characters.is_empty() {
                     return Token::new(TokenType::Whitespace {
                         characters: " ".into(),
                     });
                 }
-
-                return token;
             }
 
-            return token;
+            token
         }));
 
         TokenReference::new(leading_trivia, node.token().clone(), trailing_trivia)
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::build::minify;
+use crate::config::Config;
+use crate::diagnostic::Diagnostic;
+use crate::graph::ModuleGraph;
+use crate::package::Package;
+use crate::parser::RequireVisitor;
+use crate::vfs::FileSystem;
+
+/// What to bundle, and how
+#[derive(Clone, Debug)]
+pub struct BundleOptions {
+    pub src_dir: PathBuf,
+    pub entry_file: String, // relative to the source directory, without extension
+
+    /// Whether the development modules are bundled, and `dev` is defined
+    pub development: bool,
+    pub dev_files: Vec<String>,
+
+    /// The names `--#if` conditions are evaluated against
+    pub defines: BTreeMap<String, bool>,
+    /// Whether every `--#if` branch is kept instead, to find the modules any profile requires
+    pub all_branches: bool,
+    pub minify: bool,
+
+    /// Where the visited modules are kept between runs, if anywhere
+    pub cache_dir: Option<PathBuf>,
+
+    /// How many modules are read at once
+    pub jobs: usize,
+
+    /// The local dependencies and Wally folders, their modules required by name
+    pub packages: Vec<Package>,
+}
+
+impl BundleOptions {
+    /// A production build of the entry file, without minifying nor caching
+    pub fn new(src_dir: impl Into<PathBuf>, entry_file: &str) -> Self {
+        Self {
+            src_dir: src_dir.into(),
+            entry_file: entry_file.to_string(),
+            development: false,
+            dev_files: Vec::new(),
+            defines: BTreeMap::new(),
+            all_branches: false,
+            minify: false,
+            cache_dir: None,
+            jobs: 1,
+            packages: Vec::new(),
+        }
+    }
+
+    /// The options of a project, with the settings of its selected profile
+    pub fn from_config(config: &Config, development: bool) -> Self {
+        let jobs = config.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+        });
+
+        Self {
+            src_dir: config.src_path(),
+            entry_file: config.entry_file.clone(),
+            development,
+            dev_files: config.settings.dev_files.clone(),
+            defines: config.settings.active_defines(),
+            all_branches: false,
+            minify: config.settings.minify,
+            cache_dir: config.cache_path(),
+            jobs,
+            packages: [config.packages.clone(), config.wally_packages()].concat(),
+        }
+    }
+}
+
+/// Maps the lines of a bundle to its modules: the bundle's code up to `sources[i]` comes from
+/// `files[i]`, the first file being the bundler itself
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct SourceMap {
+    pub files: Vec<String>,
+    pub sources: Vec<usize>,
+}
+
+/// A bundle, and what is known about it
+#[derive(Clone, PartialEq, Debug)]
+pub struct BundleOutput {
+    pub code: String,
+    pub source_map: SourceMap,
+    pub modules: Vec<String>, // in the order they are in the bundle
+    pub diagnostics: Vec<Diagnostic>, // the warnings about the bundled modules
+}
+
+/// Everything that can stop a bundle from being made
+#[derive(Debug)]
+pub enum BundleError {
+    /// A module could not be found, read or parsed
+    Module(Diagnostic),
+    /// A production module imports a development module
+    DevImport { importer: String, module: String },
+    /// The bundle could not be minified
+    Minify(full_moon::Error),
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleError::Module(diagnostic) => write!(f, "{}", diagnostic),
+            BundleError::DevImport { importer, module } => write!(
+                f,
+                "Production module '{}' imports the development module '{}'",
+                importer, module
+            ),
+            BundleError::Minify(err) => write!(f, "Could not minify the bundle: {}", err),
+        }
+    }
+}
+
+impl Error for BundleError {}
+
+impl BundleError {
+    /// The error as a diagnostic, for the tools that only show those
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            BundleError::Module(diagnostic) => diagnostic.clone(),
+            BundleError::DevImport { importer, .. } => {
+                Diagnostic::error("dev-import", self.to_string()).with_module(importer)
+            }
+            BundleError::Minify(_) => Diagnostic::error("minify", self.to_string()),
+        }
+    }
+}
+
+impl From<Diagnostic> for BundleError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        BundleError::Module(diagnostic)
+    }
+}
+
+/// Bundles a project. Modules are kept between bundles, so only the invalidated ones are read again
+pub struct Bundler {
+    options: BundleOptions,
+    visitor: RequireVisitor,
+}
+
+impl Bundler {
+    pub fn new(options: BundleOptions) -> Self {
+        let mut visitor = RequireVisitor::new(
+            &options.src_dir.to_string_lossy(),
+            &options.entry_file,
+            options.development,
+            options.defines.clone(),
+        )
+        .with_jobs(options.jobs)
+        .with_packages(&options.packages);
+
+        if options.all_branches {
+            visitor = visitor.with_all_branches();
+        }
+
+        if let Some(cache_dir) = &options.cache_dir {
+            visitor = visitor.with_cache(cache_dir);
+        }
+
+        Self { options, visitor }
+    }
+
+    /// Reads the modules from the given file system instead of the disk
+    pub fn with_fs(self, fs: impl FileSystem + 'static) -> Self {
+        Self {
+            visitor: self.visitor.with_fs(Arc::new(fs)),
+            ..self
+        }
+    }
+
+    pub fn options(&self) -> &BundleOptions {
+        &self.options
+    }
+
+    /// The parser, which knows about every module visited so far
+    pub fn visitor(&self) -> &RequireVisitor {
+        &self.visitor
+    }
+
+    /// The source directory, as modules are resolved from it
+    pub fn src_dir(&self) -> String {
+        self.options.src_dir.to_string_lossy().to_string()
+    }
+
+    /// The file of a module, relative to the current directory
+    pub fn module_file(&self, module: &str) -> Option<String> {
+        self.visitor.module_file(module)
+    }
+
+    /// The modules the bundle is built from: the entry file, then the development modules
+    pub fn roots(&self) -> Vec<String> {
+        self.visitor.roots(&self.options.dev_files)
+    }
+
+    /// Bundles the project, minified if the options say so
+    pub fn bundle(&mut self) -> Result<BundleOutput, BundleError> {
+        let mut output = self.visitor.generate_bundle(&self.options.dev_files)?;
+
+        if self.options.minify {
+            output.code = minify(&output.code).map_err(BundleError::Minify)?;
+        }
+
+        output.diagnostics = output
+            .modules
+            .iter()
+            .flat_map(|module| self.visitor.dynamic_require_warnings(module))
+            .collect();
+
+        Ok(output)
+    }
+
+    /// Forgets a module, so it is read again by the next bundle
+    pub fn invalidate(&mut self, module: &str) {
+        self.visitor.mark_file_change(module);
+    }
+
+    /// Forgets every module in a directory, returning how many were known
+    pub fn invalidate_dir(&mut self, dir: &str) -> usize {
+        self.visitor.mark_dir_change(dir)
+    }
+
+    /// Whether a change to a module can change the bundle: it's bundled, required by a visited
+    /// module, or one of the roots
+    pub fn depends_on(&self, module: &str) -> bool {
+        self.visitor.module_code(module).is_some()
+            || !self.visitor.importers(module).is_empty()
+            || self.options.entry_file == module
+            || self.options.dev_files.iter().any(|dev_file| dev_file == module)
+    }
+
+    /// Same as `depends_on`, for any module in a directory
+    pub fn depends_on_dir(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        let inside = |module: &String| dir.is_empty() || module == dir || module.starts_with(&prefix);
+
+        self.visitor
+            .imports()
+            .iter()
+            .any(|(module, imports)| inside(module) || imports.iter().any(inside))
+            || inside(&self.options.entry_file)
+            || self.options.dev_files.iter().any(inside)
+    }
+
+    /// The modules reachable from the roots, and their imports
+    pub fn graph(&mut self) -> Result<ModuleGraph, BundleError> {
+        let modules = self.visitor.traverse(&self.roots())?;
+        Ok(ModuleGraph::new(modules, self.visitor.imports()))
+    }
+
+    /// Reads every module reachable from the roots, collecting all the problems found instead of
+    /// stopping at the first one. The graph leaves out the modules that could not be read
+    pub fn check(&mut self) -> (ModuleGraph, Vec<Diagnostic>) {
+        let (modules, diagnostics) = self.visitor.check(&self.roots());
+        (ModuleGraph::new(modules, self.visitor.imports()), diagnostics)
+    }
+}
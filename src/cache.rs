@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The directory of the cache, at the root of the project
+pub const CACHE_DIR_NAME: &str = ".luajoin-cache";
+
+/// Bumped when modules are transformed differently within a version, so older entries are ignored
+const FORMAT: u32 = 2;
+
+/// What the bundler keeps of a visited module
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CachedModule {
+    pub code: String, // the transformed code, as it is put in the bundle
+    pub imports: Vec<String>,
+    pub import_lines: HashMap<String, usize>,
+    pub dynamic_requires: Vec<usize>,
+}
+
+/// A cached module, and everything that must match for it to be used
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    version: String,
+    #[serde(default)]
+    format: u32,
+    module: String,
+    file: String, // relative to the source directory
+    defines: u64,
+    source: u64,
+    source_len: usize,
+    checksum: u64, // of the cached module, to tell a corrupted entry apart
+    cached: CachedModule,
+}
+
+/// Transformed modules saved on disk between runs, keyed by the content of their source file, the
+/// version of luajoin and the defines they were transformed with
+pub struct Cache {
+    dir: PathBuf,
+    defines: u64,
+}
+
+impl Cache {
+    /// The packages change how some requires are read, so they're part of the defines' key
+    pub fn new(dir: &Path, defines: &BTreeMap<String, bool>, packages: &[String]) -> Self {
+        let defines = serde_json::to_string(defines).unwrap_or_default();
+        let packages = packages.join("\n");
+
+        Self {
+            dir: dir.to_path_buf(),
+            defines: hash(&[defines.as_bytes(), packages.as_bytes()]),
+        }
+    }
+
+    /// Each module and set of defines has a single entry, overwritten when the source changes
+    fn entry_path(&self, module: &str) -> PathBuf {
+        let name = hash(&[module.as_bytes(), &self.defines.to_le_bytes()]);
+        self.dir.join(format!("{:016x}.json", name))
+    }
+
+    /// Gets a module, if it was cached from the same source with the same settings. Stale and
+    /// corrupted entries are ignored
+    pub fn get(&self, module: &str, file: &str, source: &str) -> Option<CachedModule> {
+        let content = fs::read_to_string(self.entry_path(module)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&content).ok()?;
+
+        let matches = entry.version == env!("CARGO_PKG_VERSION")
+            && entry.format == FORMAT
+            && entry.module == module
+            && entry.file == file
+            && entry.defines == self.defines
+            && entry.source_len == source.len()
+            && entry.source == hash(&[source.as_bytes()])
+            && entry.checksum == checksum(&entry.cached);
+
+        matches.then_some(entry.cached)
+    }
+
+    /// Saves a module. The cache only speeds things up, so failing to write it is not an error
+    pub fn put(&self, module: &str, file: &str, source: &str, cached: &CachedModule) {
+        let entry = CacheEntry {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            format: FORMAT,
+            module: module.to_string(),
+            file: file.to_string(),
+            defines: self.defines,
+            source: hash(&[source.as_bytes()]),
+            source_len: source.len(),
+            checksum: checksum(cached),
+            cached: cached.clone(),
+        };
+
+        let content = match serde_json::to_string(&entry) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+
+        // Write to a temporary file first, so an interrupted write never leaves a partial entry
+        let path = self.entry_path(module);
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        if fs::create_dir_all(&self.dir).is_err() || fs::write(&temp_path, content).is_err() {
+            return;
+        }
+
+        if fs::rename(&temp_path, &path).is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+}
+
+fn checksum(cached: &CachedModule) -> u64 {
+    let mut import_lines: Vec<(&String, &usize)> = cached.import_lines.iter().collect();
+    import_lines.sort();
+
+    let imports = cached.imports.join("\n");
+    let lines = format!("{:?}{:?}", import_lines, cached.dynamic_requires);
+
+    hash(&[cached.code.as_bytes(), imports.as_bytes(), lines.as_bytes()])
+}
+
+/// FNV-1a, which is stable across builds unlike the standard library's hasher. Each part is
+/// prefixed by its length, so moving bytes from a part to the next changes the hash
+fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_stale_and_corrupted_entries() {
+        let dir = std::env::temp_dir().join(format!("luajoin-cache-test-{}", std::process::id()));
+        let cache = Cache::new(&dir, &BTreeMap::new(), &[]);
+        let cached = CachedModule {
+            code: String::from("return 1"),
+            imports: vec![String::from("util")],
+            import_lines: HashMap::from([(String::from("util"), 1)]),
+            dynamic_requires: Vec::new(),
+        };
+
+        cache.put("main", "/main.lua", "return 1", &cached);
+        assert_eq!(cache.get("main", "/main.lua", "return 1"), Some(cached.clone()));
+        assert_eq!(cache.get("main", "/main.lua", "return 2"), None);
+
+        let dev_cache = Cache::new(&dir, &BTreeMap::from([(String::from("dev"), true)]), &[]);
+        assert_eq!(dev_cache.get("main", "/main.lua", "return 1"), None);
+
+        // Tamper with the cached code
+        let path = cache.entry_path("main");
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace("return 1\"", "return 3\"")).unwrap();
+        assert_eq!(cache.get("main", "/main.lua", "return 1"), None);
+
+        fs::write(&path, "{").unwrap();
+        assert_eq!(cache.get("main", "/main.lua", "return 1"), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,22 +1,31 @@
-use crate::build::BuildVisitor;
-use crate::config::Config;
-use crate::parser::RequireVisitor;
+use crate::console;
 use colorize::AnsiColor;
-use full_moon::visitors::VisitorMut;
-use notify::RecursiveMode;
-use notify_debouncer_mini::new_debouncer;
+use luajoin::config::WatchSettings;
+use luajoin::diagnostic::Diagnostic;
+use luajoin::graph::{GraphFormat, ModuleGraph};
+use luajoin::package::Lockfile;
+use luajoin::size::SizeReport;
+use luajoin::watch::{self, Change, IgnoreFilter, PathMapper};
+use luajoin::{unused, BundleOptions, BundleOutput, Bundler, Config, MemoryFs, SourceMap};
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_mini::{new_debouncer, new_debouncer_opt, DebounceEventResult, Debouncer};
 use serde::{Deserialize, Serialize};
 use simple_websockets::{Event, Message, Responder};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use std::{collections::HashMap, env, fs, io, path::Path, time::SystemTime};
-
-use crate::console;
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use std::{collections::HashMap, fs, io, time::SystemTime};
 
-#[derive(Serialize, Deserialize)]
-struct SourceMaps {
-    pub files: Vec<String>,
-    pub sources: Vec<usize>,
+/// How reports are printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,52 +35,77 @@ struct ErrorLog {
     pub message_content: String,
 }
 
-fn make_bundle(parser: &mut RequireVisitor, config: &Config) {
+/// Writes the source map of a bundle next to it
+fn write_source_map(bundle_path: &Path, source_map: &SourceMap) -> Result<(), Box<dyn Error>> {
+    let src_map_json = serde_json::to_string(source_map)?;
+
+    fs::write(bundle_path.with_extension("lua.map"), src_map_json)?;
+    Ok(())
+}
+
+/// Creates the bundler for the project, with the settings of its profile
+fn new_bundler(config: &Config, development: bool) -> Bundler {
+    Bundler::new(BundleOptions::from_config(config, development))
+}
+
+/// A development bundler for the commands that only read the project, so nothing is cached
+fn read_only_bundler(config: &Config) -> Bundler {
+    let mut options = BundleOptions::from_config(config, true);
+    options.cache_dir = None;
+
+    Bundler::new(options)
+}
+
+/// Same, but keeping every `--#if` branch, so the modules any profile requires are reached
+fn all_branches_bundler(config: &Config) -> Bundler {
+    let mut options = BundleOptions::from_config(config, true);
+    options.cache_dir = None;
+    options.all_branches = true;
+
+    Bundler::new(options)
+}
+
+/// Writes the development bundle, with the source map
+fn write_bundle(bundler: &mut Bundler, config: &Config) -> Result<BundleOutput, Diagnostic> {
     // If the output directory does not exist, create it
-    if !Path::new(&config.out_dir).exists() {
-        fs::create_dir(&config.out_dir).unwrap();
+    if !config.out_path().exists() {
+        fs::create_dir_all(config.out_path()).unwrap();
     }
 
     let start_time = SystemTime::now();
 
     // Build the file project
-    let (bundle_result, source_maps, imports) = match parser.generate_bundle(true) {
-        Ok(bundle) => bundle,
+    let output = match bundler.bundle() {
+        Ok(output) => output,
         Err(err) => {
             console::log_error(&format!("Problem generating bundle: {}", err));
-            return;
+            return Err(err.to_diagnostic());
         }
     };
 
+    for diagnostic in &output.diagnostics {
+        console::log(&diagnostic.to_string().yellow());
+    }
+
     // Write the bundle to the output file
-    match fs::write(
-        &(config.out_dir.to_owned() + "/bundle.dev.lua"),
-        &bundle_result,
-    ) {
+    let bundle_path = config.out_path().join("bundle.dev.lua");
+    match fs::write(&bundle_path, &output.code) {
         Ok(_) => (),
         Err(err) => {
-            console::log_error(&format!("Problem writing bundle: {}", err));
-            return;
+            let message = format!("Problem writing bundle: {}", err);
+            console::log_error(&message);
+            return Err(Diagnostic::error("write", message));
         }
     };
 
     // Write the source map too
-    let src_map = SourceMaps {
-        files: imports,
-        sources: source_maps,
-    };
-
-    let src_map_json = serde_json::to_string(&src_map).unwrap();
-    match fs::write(
-        &(config.out_dir.to_owned() + "/bundle.dev.lua.map"),
-        &src_map_json,
-    ) {
-        Ok(_) => (),
-        Err(err) => {
-            console::log_error(&format!("Problem writing source map: {}", err));
-            return;
+    if config.settings.writes_source_map() {
+        if let Err(err) = write_source_map(&bundle_path, &output.source_map) {
+            let message = format!("Problem writing source map: {}", err);
+            console::log_error(&message);
+            return Err(Diagnostic::error("write", message));
         }
-    };
+    }
 
     console::log(
         &format!(
@@ -80,12 +114,99 @@ fn make_bundle(parser: &mut RequireVisitor, config: &Config) {
         )
         .green(),
     );
+
+    Ok(output)
+}
+
+/// Writes the development bundle and tells the clients how it went, returning its modules unless
+/// it failed
+fn make_bundle(bundler: &mut Bundler, config: &Config, server: &SharedServer) -> Option<Vec<String>> {
+    let result = write_bundle(bundler, config);
+
+    let status = match &result {
+        Ok(output) => BuildStatus::Ok(with_frames(bundler, &output.diagnostics)),
+        Err(diagnostic) => BuildStatus::Failed(with_frames(bundler, std::slice::from_ref(diagnostic))),
+    };
+
+    server.lock().unwrap().set_status(status);
+    result.ok().map(|output| output.modules)
+}
+
+/// A diagnostic as the clients get it, with its code frame
+#[derive(Serialize)]
+struct ClientDiagnostic {
+    #[serde(flatten)]
+    diagnostic: Diagnostic,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame: Option<String>,
+}
+
+/// Adds the code frames to the diagnostics, from the files as the bundler reads them
+fn with_frames(bundler: &Bundler, diagnostics: &[Diagnostic]) -> Vec<ClientDiagnostic> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let frame = diagnostic
+                .file
+                .as_ref()
+                .and_then(|file| bundler.visitor().fs().read_to_string(Path::new(file)).ok())
+                .and_then(|source| diagnostic.code_frame(&source));
+
+            ClientDiagnostic {
+                diagnostic: diagnostic.clone(),
+                frame,
+            }
+        })
+        .collect()
+}
+
+/// The outcome of the last development build
+#[derive(Default)]
+enum BuildStatus {
+    #[default]
+    Pending,
+    Ok(Vec<ClientDiagnostic>), // the warnings
+    Failed(Vec<ClientDiagnostic>),
+}
+
+impl BuildStatus {
+    /// The message telling a client about it, `build_ok` or `build_error` with the diagnostics
+    fn message(&self) -> Option<String> {
+        let (event, diagnostics) = match self {
+            BuildStatus::Pending => return None,
+            BuildStatus::Ok(diagnostics) => ("build_ok", diagnostics),
+            BuildStatus::Failed(diagnostics) => ("build_error", diagnostics),
+        };
+
+        let content = serde_json::json!({ "diagnostics": diagnostics }).to_string();
+        Some(serde_json::to_string(&vec![String::from(event), content]).unwrap())
+    }
+}
+
+/// The connected clients, and the outcome of the last build which they're told about
+#[derive(Default)]
+pub struct ServerState {
+    clients: HashMap<u64, Responder>,
+    status: BuildStatus,
+}
+
+pub type SharedServer = Arc<Mutex<ServerState>>;
+
+impl ServerState {
+    fn set_status(&mut self, status: BuildStatus) {
+        self.status = status;
+
+        if let Some(message) = self.status.message() {
+            for responder in self.clients.values() {
+                responder.send(Message::Text(message.clone()));
+            }
+        }
+    }
 }
 
 fn map_to_source(line: usize, config: &Config) -> Option<(String, usize)> {
-    let source_map =
-        fs::read_to_string(&(config.out_dir.to_owned() + "/bundle.dev.lua.map")).unwrap();
-    let source_map: SourceMaps = serde_json::from_str(&source_map).unwrap();
+    let source_map = fs::read_to_string(config.out_path().join("bundle.dev.lua.map")).ok()?;
+    let source_map: SourceMap = serde_json::from_str(&source_map).ok()?;
 
     // Go through the line, find if the current one is larger
     for (i, &cur_line) in source_map.sources.iter().enumerate() {
@@ -100,29 +221,30 @@ fn map_to_source(line: usize, config: &Config) -> Option<(String, usize)> {
     None
 }
 
-pub fn run_server(config: Config) {
+/// Runs the websocket server and the prompt. The config is shared with the bundler, which replaces
+/// it when the project file changes, and so are the clients, which it tells about every build
+pub fn run_server(config: Arc<RwLock<Config>>, server: SharedServer, port: u16) {
     let config_2 = config.clone();
 
     std::thread::spawn(move || {
         std::thread::scope(|f| {
-            let clients = Arc::new(Mutex::new(HashMap::<u64, Responder>::new()));
-            let clients_clone = clients.clone();
+            let server_2 = server.clone();
 
             // Create a new thread for the websocket server
             f.spawn(move || {
-                let event_hub =
-                    simple_websockets::launch(1338).expect("failed to listen on port 1338");
-                console::log("Server started on port 1338!");
+                let event_hub = simple_websockets::launch(port)
+                    .unwrap_or_else(|_| panic!("failed to listen on port {}", port));
+                console::log(&format!("Server started on port {}!", port));
 
                 loop {
                     match event_hub.poll_event() {
                         Event::Connect(client_id, responder) => {
-                            let mut clients_map = clients_clone.lock().unwrap();
-                            clients_map.insert(client_id, responder);
+                            let mut server = server_2.lock().unwrap();
+                            server.clients.insert(client_id, responder);
                         }
                         Event::Disconnect(client_id) => {
-                            let mut clients_map = clients_clone.lock().unwrap();
-                            clients_map.remove(&client_id);
+                            let mut server = server_2.lock().unwrap();
+                            server.clients.remove(&client_id);
                         }
                         Event::Message(client_id, message) => {
                             if let Message::Text(text) = message {
@@ -137,7 +259,7 @@ pub fn run_server(config: Config) {
 
                                 // Get the message data
                                 let message_vec = message_json.unwrap();
-                                let message_type = message_vec.get(0).unwrap();
+                                let message_type = message_vec.first().unwrap();
 
                                 match message_type.as_str() {
                                     "connected" => {
@@ -149,10 +271,18 @@ pub fn run_server(config: Config) {
                                             client_name.clone().green(),
                                             client_id
                                         ));
+
+                                        // Tell it how the last build went
+                                        let server = server_2.lock().unwrap();
+                                        if let (Some(responder), Some(message)) =
+                                            (server.clients.get(&client_id), server.status.message())
+                                        {
+                                            responder.send(Message::Text(message));
+                                        }
                                     }
                                     "error" => {
                                         let error_data: ErrorLog =
-                                            serde_json::from_str(&message_vec.get(1).unwrap())
+                                            serde_json::from_str(message_vec.get(1).unwrap())
                                                 .unwrap();
 
                                         // Format the header
@@ -160,7 +290,7 @@ pub fn run_server(config: Config) {
 
                                         // Format the header, the error
                                         for line in error_data.message_lines {
-                                            let (file, rel_line) = map_to_source(line, &config_2)
+                                            let (file, rel_line) = map_to_source(line, &config_2.read().unwrap())
                                                 .unwrap_or_else(|| ("Unknown".to_string(), 0));
 
                                             header_lines
@@ -181,7 +311,7 @@ pub fn run_server(config: Config) {
                                             vec![error_header, "\tStack Begin".to_string()];
 
                                         for line in error_data.stack_trace_lines {
-                                            let (file, rel_line) = map_to_source(line, &config_2)
+                                            let (file, rel_line) = map_to_source(line, &config_2.read().unwrap())
                                                 .unwrap_or_else(|| ("Unknown".to_string(), 0));
 
                                             display_lines.push(
@@ -213,8 +343,12 @@ pub fn run_server(config: Config) {
                         .read_line(&mut input)
                         .expect("Failed to read line");
 
-                    let clients = clients.lock().unwrap();
+                    let server = server.lock().unwrap();
+                    let clients = &server.clients;
                     match input.trim().to_lowercase().as_str() {
+                        "e" if matches!(server.status, BuildStatus::Failed(_)) => {
+                            console::log_error("The last build failed, fix it before executing");
+                        }
                         "e" => {
                             console::log(&format!(
                                 "Executing bundle for {} clients...",
@@ -222,9 +356,9 @@ pub fn run_server(config: Config) {
                             ));
 
                             // Read the bundle
-                            let bundle =
-                                fs::read_to_string(config.out_dir.to_owned() + "/bundle.dev.lua")
-                                    .unwrap();
+                            let bundle_path =
+                                config.read().unwrap().out_path().join("bundle.dev.lua");
+                            let bundle = fs::read_to_string(bundle_path).unwrap();
 
                             let send_message =
                                 serde_json::to_string(&vec![String::from("exec"), bundle]).unwrap();
@@ -243,100 +377,804 @@ pub fn run_server(config: Config) {
     });
 }
 
-pub fn run_bundler(config: Config) {
+/// Everything `serve` watches: the source directory, the directory of the project file, the extra
+/// paths of the project and the source directories or archives of its dependencies, and the
+/// directories of the module files symlinked from outside the source directory
+struct WatchSet {
+    mapper: PathMapper,
+    ignore: IgnoreFilter,
+    project_file: Option<PathBuf>,
+    project_dir: PathBuf,
+    extra_paths: Vec<PathBuf>,
+    archives: Vec<PathBuf>, // extracted as the project file is read
+    linked_files: HashMap<PathBuf, String>, // the module of each file outside the source directory
+    watched: Vec<(PathBuf, RecursiveMode)>,
+}
+
+impl WatchSet {
+    fn new(config: &Config) -> Self {
+        let mut extra_paths = Vec::new();
+        for path in &config.watch.paths {
+            match fs::canonicalize(config.root.join(path)) {
+                Ok(path) => extra_paths.push(path),
+                Err(err) => console::log_error(&format!("Could not watch '{}': {}", path, err)),
+            }
+        }
+
+        // The modules of the dependencies are named after them, so any of their changes rebuilds
+        let mut archives = Vec::new();
+        for package in config.packages.iter().chain(&config.wally_packages()) {
+            let path = package.archive.as_ref().unwrap_or(&package.src_dir);
+            if let Some(archive) = package.archive.as_deref().and_then(watch::canonical) {
+                archives.push(archive);
+            }
+
+            match fs::canonicalize(path) {
+                Ok(path) => extra_paths.push(path),
+                Err(err) => console::log_error(&format!(
+                    "Could not watch the dependency '{}': {}",
+                    package.name, err
+                )),
+            }
+        }
+
+        Self {
+            mapper: PathMapper::new(&config.src_path()),
+            ignore: IgnoreFilter::new(config),
+            project_file: watch::canonical(&config.path),
+            project_dir: watch::canonical_dir(&config.root),
+            extra_paths,
+            archives,
+            linked_files: HashMap::new(),
+            watched: Vec::new(),
+        }
+    }
+
+    /// Remembers the bundled modules whose files are outside of the source directory
+    fn link_modules(&mut self, bundler: &Bundler, modules: &[String]) {
+        self.linked_files = modules
+            .iter()
+            .filter_map(|module| {
+                let file = fs::canonicalize(bundler.module_file(module)?).ok()?;
+                (!file.starts_with(self.mapper.watch_root())).then(|| (file, module.clone()))
+            })
+            .collect();
+    }
+
+    /// The paths to watch, leaving out the ones inside of a recursively watched directory
+    fn targets(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        let mut targets = vec![(self.mapper.watch_root().to_path_buf(), RecursiveMode::Recursive)];
+        let mut candidates = vec![(self.project_dir.clone(), RecursiveMode::NonRecursive)];
+
+        // Files are watched through their directory, as editors often replace them
+        for path in self.extra_paths.iter().chain(self.linked_files.keys()) {
+            match (path.is_dir(), path.parent()) {
+                (true, _) => candidates.push((path.clone(), RecursiveMode::Recursive)),
+                (false, Some(parent)) => candidates.push((parent.to_path_buf(), RecursiveMode::NonRecursive)),
+                (false, None) => (),
+            }
+        }
+
+        candidates.sort_by_key(|(path, mode)| (*mode != RecursiveMode::Recursive, path.clone()));
+        for (path, mode) in candidates {
+            let covered = targets.iter().any(|(target, target_mode)| {
+                *target == path || (*target_mode == RecursiveMode::Recursive && path.starts_with(target))
+            });
+
+            if !covered {
+                targets.push((path, mode));
+            }
+        }
+
+        targets
+    }
+
+    /// Makes the watcher watch the targets, and only them
+    fn sync(&mut self, watcher: &mut dyn Watcher) {
+        let targets = self.targets();
+
+        for (path, mode) in std::mem::take(&mut self.watched) {
+            if targets.contains(&(path.clone(), mode)) {
+                self.watched.push((path, mode));
+            } else {
+                let _ = watcher.unwatch(&path);
+            }
+        }
+
+        for (path, mode) in targets {
+            if self.watched.contains(&(path.clone(), mode)) {
+                continue;
+            }
+
+            match watcher.watch(&path, mode) {
+                Ok(_) => self.watched.push((path, mode)),
+                Err(err) => console::log_error(&format!("Could not watch '{}': {}", path.display(), err)),
+            }
+        }
+    }
+
+    fn watches_src_dir(&self) -> bool {
+        let src_dir = self.mapper.watch_root();
+        self.watched.iter().any(|(path, _)| path == src_dir)
+    }
+
+    /// Whether the project file, or the archive of a dependency, changed
+    fn is_project_file(&self, path: &Path) -> bool {
+        let path = watch::canonical(path);
+        (self.project_file.is_some() && path == self.project_file)
+            || path.is_some_and(|path| self.archives.contains(&path))
+    }
+
+    /// The extra path a changed path is part of
+    fn extra_path(&self, path: &Path) -> Option<&PathBuf> {
+        let path = watch::canonical(path)?;
+        self.extra_paths.iter().find(|extra| path.starts_with(extra))
+    }
+
+    /// The change a path is part of, for a module of the bundle
+    fn change(&self, path: &Path) -> Result<Option<Change>, String> {
+        let linked = watch::canonical(path).and_then(|path| self.linked_files.get(&path));
+        match linked {
+            Some(module) => Ok(Some(Change::Module(module.clone()))),
+            None => self.mapper.map(path),
+        }
+    }
+}
+
+/// A file watcher, with its events debounced
+enum FileWatcher {
+    Native(Debouncer<RecommendedWatcher>),
+    Poll(Debouncer<PollWatcher>),
+}
+
+impl FileWatcher {
+    /// Starts the system's watcher, or polls if the settings say so or the system's watcher fails
+    fn start(settings: &WatchSettings, tx: &Sender<DebounceEventResult>) -> Result<Self, String> {
+        if !settings.polls() {
+            match new_debouncer(settings.debounce(), None, tx.clone()) {
+                Ok(debouncer) => return Ok(FileWatcher::Native(debouncer)),
+                Err(err) => console::log_error(&format!(
+                    "Could not start the file watcher, polling instead: {}",
+                    err
+                )),
+            }
+        }
+
+        Self::poll(settings, tx)
+    }
+
+    fn poll(settings: &WatchSettings, tx: &Sender<DebounceEventResult>) -> Result<Self, String> {
+        let config = notify::Config::default().with_poll_interval(settings.poll_interval());
+
+        console::log(&format!(
+            "Polling for changes every {}ms",
+            settings.poll_interval().as_millis()
+        ));
+
+        new_debouncer_opt::<_, PollWatcher>(settings.debounce(), None, tx.clone(), config)
+            .map(FileWatcher::Poll)
+            .map_err(|err| format!("Could not start the file watcher: {}", err))
+    }
+
+    fn watcher(&mut self) -> &mut dyn Watcher {
+        match self {
+            FileWatcher::Native(debouncer) => debouncer.watcher(),
+            FileWatcher::Poll(debouncer) => debouncer.watcher(),
+        }
+    }
+
+    /// Watches the set, polling instead if the system's watcher can't watch the source directory
+    fn sync(
+        &mut self,
+        watch_set: &mut WatchSet,
+        settings: &WatchSettings,
+        tx: &Sender<DebounceEventResult>,
+    ) -> Result<(), String> {
+        watch_set.sync(self.watcher());
+
+        if let FileWatcher::Native(_) = self {
+            if !watch_set.watches_src_dir() {
+                console::log_error("The file watcher failed, polling instead");
+
+                *self = Self::poll(settings, tx)?;
+                watch_set.watched.clear();
+                watch_set.sync(self.watcher());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Watches the source directory and the project file, rebuilding the development bundle on
+/// changes. The project file is read again with `reload`, and kept as it was if it's invalid
+pub fn run_bundler(
+    mut config: Config,
+    shared_config: Arc<RwLock<Config>>,
+    server: SharedServer,
+    reload: impl Fn() -> Result<(Config, Vec<String>), String>,
+) {
+    log_profile(&config);
+
     // Create the parser
-    let mut require_visitor = RequireVisitor::new(&config.src_dir, &config.entry_file);
-    make_bundle(&mut require_visitor, &config);
+    let mut bundler = new_bundler(&config, true);
+    let mut bundled = make_bundle(&mut bundler, &config, &server);
 
     // Create the bundler
     let (tx, rx) = std::sync::mpsc::channel();
 
-    let mut debouncer = new_debouncer(Duration::from_millis(100), None, tx).unwrap();
+    // Event paths are absolute and may go through symlinks, the set makes module ids of them
+    let mut watch_set = WatchSet::new(&config);
+    if let Some(modules) = &bundled {
+        watch_set.link_modules(&bundler, modules);
+    }
+
+    let mut file_watcher = match FileWatcher::start(&config.watch, &tx)
+        .and_then(|mut file_watcher| {
+            file_watcher.sync(&mut watch_set, &config.watch, &tx)?;
+            Ok(file_watcher)
+        }) {
+        Ok(file_watcher) => file_watcher,
+        Err(err) => {
+            console::log_error(&err);
+            process::exit(1);
+        }
+    };
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(errors) => {
+                for err in errors {
+                    console::log_error(&format!("File watcher error: {}", err));
+                }
+
+                continue;
+            }
+        };
+
+        // The project file changed, everything is read again with the new settings
+        if events.iter().any(|event| watch_set.is_project_file(&event.path)) {
+            let (new_config, warnings) = match reload() {
+                Ok(reloaded) => reloaded,
+                Err(err) => {
+                    console::log_error(&format!(
+                        "Invalid project file, the previous one is still used: {}",
+                        err
+                    ));
+                    continue;
+                }
+            };
+
+            console::log(&"Project file changed, reloading...".blue());
+            for warning in warnings {
+                console::log_warning(&warning);
+            }
+
+            // A new watcher is needed if the way of watching changed
+            let restart = config.watch.polls() != new_config.watch.polls()
+                || config.watch.poll_interval() != new_config.watch.poll_interval()
+                || config.watch.debounce() != new_config.watch.debounce();
+
+            config = new_config;
+            *shared_config.write().unwrap() = config.clone();
+
+            log_profile(&config);
+            bundler = new_bundler(&config, true);
+            bundled = make_bundle(&mut bundler, &config, &server);
+
+            let watched = std::mem::take(&mut watch_set.watched);
+            watch_set = WatchSet::new(&config);
+            watch_set.watched = watched;
+            if let Some(modules) = &bundled {
+                watch_set.link_modules(&bundler, modules);
+            }
 
-    debouncer
-        .watcher()
-        .watch(Path::new(&config.src_dir), RecursiveMode::Recursive)
-        .unwrap();
+            if restart {
+                match FileWatcher::start(&config.watch, &tx) {
+                    Ok(new_watcher) => {
+                        file_watcher = new_watcher;
+                        watch_set.watched.clear();
+                    }
+                    Err(err) => console::log_error(&err),
+                }
+            }
 
-    for e in rx {
-        let e = e.unwrap();
+            if let Err(err) = file_watcher.sync(&mut watch_set, &config.watch, &tx) {
+                console::log_error(&err);
+            }
+
+            continue;
+        }
 
         // Debounced event, go through each file
-        let mut marked_file_count = 0;
-        for event in &e {
+        let mut changed = false;
+        for event in &events {
             // Make sure it's Any and not AnyContinuous
-            if let notify_debouncer_mini::DebouncedEventKind::Any = event.kind {
-                let file_name = event.path.to_str().unwrap().to_string();
-
-                marked_file_count += 1;
-
-                // Parse the file's relative path in the project
-                let cur_dir = env::current_dir().unwrap().to_str().unwrap().to_string();
-
-                // Find the offset, then get the relative file
-                let file_offset = cur_dir.len() + config.src_dir.len() + 2;
-                let relative_file = file_name[file_offset..].to_string().replace("\\", "/");
-                let without_ext: String;
-
-                // Find the file without the extension
-                if relative_file.ends_with(".json") {
-                    // Json file: remove the .json
-                    without_ext = relative_file[..relative_file.len() - 5].to_string();
-                } else if relative_file.ends_with("/init.lua") {
-                    without_ext = relative_file[..relative_file.len() - 9].to_string();
-                } else if relative_file.ends_with(".lua") {
-                    // Lua file: remove the .lua
-                    without_ext = relative_file[..relative_file.len() - 4].to_string();
-                } else {
-                    // Not a lua file, skip
+            if let notify_debouncer_mini::DebouncedEventKind::AnyContinuous = event.kind {
+                continue;
+            }
+
+            if watch_set.ignore.ignores(&event.path) {
+                continue;
+            }
+
+            // Anything may depend on the extra paths
+            if let Some(extra) = watch_set.extra_path(&event.path) {
+                console::log(&format!("'{}' changed!", extra.display()));
+                bundler.invalidate_dir("");
+                changed = true;
+                continue;
+            }
+
+            let change = match watch_set.change(&event.path) {
+                Ok(Some(change)) => change,
+                Ok(None) => continue,
+                Err(err) => {
+                    console::log_error(&err);
                     continue;
                 }
+            };
 
-                // Mark the file as changed
-                require_visitor.mark_file_change(&without_ext);
-                console::log(&format!("File '{}' changed!", without_ext))
+            // When the last bundle failed, any new file may be the one it was missing
+            let removed = !event.path.exists();
+            match change {
+                Change::Module(module) => {
+                    if bundled.is_some() && !bundler.depends_on(&module) {
+                        continue;
+                    }
+
+                    bundler.invalidate(&module);
+                    changed = true;
+
+                    if removed {
+                        console::log(&format!("File '{}' removed!", module));
+                    } else {
+                        console::log(&format!("File '{}' changed!", module));
+                    }
+                }
+                Change::Directory(dir) => {
+                    // A removed directory only matters if some of its modules were visited
+                    let relevant = bundled.is_none() || bundler.depends_on_dir(&dir);
+                    let count = bundler.invalidate_dir(&dir);
+                    if (removed && count == 0) || !relevant {
+                        continue;
+                    }
+
+                    changed = true;
+                    if removed {
+                        console::log(&format!("Directory '{}' removed! ({} module(s))", dir, count));
+                    } else {
+                        console::log(&format!("Directory '{}' changed!", dir));
+                    }
+                }
             }
         }
 
-        if marked_file_count > 0 {
-            make_bundle(&mut require_visitor, &config);
+        if changed {
+            bundled = make_bundle(&mut bundler, &config, &server);
+
+            // The modules may have moved to other files
+            if let Some(modules) = &bundled {
+                watch_set.link_modules(&bundler, modules);
+                if let Err(err) = file_watcher.sync(&mut watch_set, &config.watch, &tx) {
+                    console::log_error(&err);
+                }
+            }
         }
     }
 }
 
-pub fn build_project(config: Config) {
-    let mut require_visitor = RequireVisitor::new(&config.src_dir, &config.entry_file);
+fn log_profile(config: &Config) {
+    console::log(&format!(
+        "Using profile '{}' (target {})",
+        config.settings.profile.clone().cyan(),
+        config.settings.target
+    ));
+}
+
+/// Builds the production bundle, which never contains the development modules. The size of each
+/// module is saved next to it, and printed if a report format is given
+pub fn build_project(config: Config, report: Option<ReportFormat>, diff: bool) {
+    verify_lockfile(&config);
+    let mut bundler = new_bundler(&config, false);
 
     // Display some info
     let start_time = Instant::now();
-    console::log("Bundling project...");
+    console::log(&format!(
+        "Bundling project with profile '{}' (target {})...",
+        config.settings.profile.clone().cyan(),
+        config.settings.target
+    ));
 
-    let (bundle_result, _, _) = match require_visitor.generate_bundle(true) {
-        Ok(bundle) => bundle,
+    let output = match bundler.bundle() {
+        Ok(output) => output,
         Err(err) => {
             console::log_error(&format!("Problem generating bundle: {}", err));
-            return;
+            process::exit(1);
         }
     };
 
-    console::log(&"Bundle complete, performing optimizations ...".green());
+    for diagnostic in &output.diagnostics {
+        console::log(&diagnostic.to_string().yellow());
+    }
 
-    // Create an AST from the bundled result
-    let ast = full_moon::parse(&bundle_result).unwrap();
-    let built_ast = BuildVisitor {}.visit_ast(ast);
-    let built_result = full_moon::print(&built_ast);
+    let sizes = SizeReport::measure(&bundler, &output);
+
+    let exceeded = sizes.exceeded_budgets(&config.budgets);
+    for message in &exceeded {
+        console::log_error(message);
+    }
+
+    if !exceeded.is_empty() {
+        process::exit(1);
+    }
 
     // Write to the file
-    let output_path = &(config.out_dir.to_owned() + "/bundle.build.lua");
-    fs::write(
-        &(config.out_dir.to_owned() + "/bundle.build.lua"),
-        &built_result,
-    )
-    .unwrap();
+    let output_path = config.out_path().join("bundle.build.lua");
+    fs::create_dir_all(config.out_path()).unwrap();
+    fs::write(&output_path, &output.code).unwrap();
+
+    // Keep the previous sizes around to compare them
+    let sizes_path = output_path.with_extension("sizes.json");
+    let previous_sizes = if diff {
+        SizeReport::load(&sizes_path)
+    } else {
+        None
+    };
+
+    if let Err(err) = sizes.save(&sizes_path) {
+        console::log_error(&format!("Problem writing size report: {}", err));
+        process::exit(1);
+    }
+
+    if config.settings.writes_source_map() {
+        if let Err(err) = write_source_map(&output_path, &output.source_map) {
+            console::log_error(&format!("Problem writing source map: {}", err));
+            process::exit(1);
+        }
+    }
 
     console::log(&format!(
         "Project compiled in {}ms! Bundle is located at '{}'",
         start_time.elapsed().as_millis(),
-        output_path.to_string().magenta()
+        output_path.display().to_string().magenta()
     ));
+
+    if diff && previous_sizes.is_none() {
+        console::log(&"No previous size report to compare with".yellow());
+    }
+
+    match report.or(diff.then_some(ReportFormat::Text)) {
+        Some(ReportFormat::Text) => console::print(&sizes.to_table(previous_sizes.as_ref())),
+        Some(ReportFormat::Json) => console::print(&sizes.to_json(previous_sizes.as_ref())),
+        None => (),
+    }
+}
+
+/// Makes sure the dependencies didn't change since they were locked, locking them on the first
+/// build. Exits otherwise
+fn verify_lockfile(config: &Config) {
+    let lock_path = config.lock_path();
+    let locked = match Lockfile::load(&lock_path) {
+        Ok(locked) => locked,
+        Err(err) => {
+            console::log_error(&err);
+            process::exit(1);
+        }
+    };
+
+    if locked.is_none() && config.packages.is_empty() {
+        return;
+    }
+
+    let current = match Lockfile::new(&config.packages) {
+        Ok(current) => current,
+        Err(err) => {
+            console::log_error(&format!("Could not read the dependencies: {}", err));
+            process::exit(1);
+        }
+    };
+
+    let locked = match locked {
+        Some(locked) => locked,
+        None => return write_lockfile(&current, &lock_path),
+    };
+
+    let changes = current.changes_since(&locked);
+    for change in &changes {
+        console::log_error(change);
+    }
+
+    if !changes.is_empty() {
+        console::log_error(&format!(
+            "The dependencies don't match '{}', run `luajoin lock` to update it",
+            lock_path.display()
+        ));
+        process::exit(1);
+    }
+}
+
+fn write_lockfile(lockfile: &Lockfile, path: &Path) {
+    match lockfile.save(path) {
+        Ok(_) => console::log(&format!(
+            "Locked {} dependencies in '{}'",
+            lockfile.packages.len(),
+            path.display()
+        )),
+        Err(err) => {
+            console::log_error(&format!("Could not write '{}': {}", path.display(), err));
+            process::exit(1);
+        }
+    }
+}
+
+/// Locks the dependencies as they are now
+pub fn lock_dependencies(config: Config) {
+    match Lockfile::new(&config.packages) {
+        Ok(lockfile) => write_lockfile(&lockfile, &config.lock_path()),
+        Err(err) => {
+            console::log_error(&format!("Could not read the dependencies: {}", err));
+            process::exit(1);
+        }
+    }
+}
+
+/// Bundles the files given on stdin, as a JSON object of their paths (relative to the project's
+/// root) to their sources, and prints the bundle. No module is read from the disk
+pub fn build_stdin(config: Config) {
+    let mut input = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut input) {
+        console::log_error(&format!("Could not read stdin: {}", err));
+        process::exit(1);
+    }
+
+    let files: HashMap<String, String> = match serde_json::from_str(&input) {
+        Ok(files) => files,
+        Err(err) => {
+            console::log_error(&format!("Invalid files on stdin: {}", err));
+            process::exit(1);
+        }
+    };
+
+    let memory_fs: MemoryFs = files
+        .into_iter()
+        .map(|(path, source)| (config.root.join(path), source))
+        .collect();
+
+    // Nothing is written either
+    let mut options = BundleOptions::from_config(&config, false);
+    options.cache_dir = None;
+
+    let mut bundler = Bundler::new(options).with_fs(memory_fs);
+    match bundler.bundle() {
+        Ok(output) => {
+            for diagnostic in &output.diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+
+            print!("{}", output.code);
+        }
+        Err(err) => {
+            console::log_error(&format!("Problem generating bundle: {}", err));
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads the modules of the project and their imports, exiting if any of them can't be read
+fn read_graph(bundler: &mut Bundler) -> ModuleGraph {
+    match bundler.graph() {
+        Ok(graph) => graph,
+        Err(err) => {
+            console::log_error(&format!("Problem reading the project: {}", err));
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints the dependency graph of the project, including the development modules
+pub fn print_graph(
+    config: Config,
+    format: GraphFormat,
+    root: Option<&str>,
+    depth: Option<usize>,
+    cycles_only: bool,
+) {
+    let mut bundler = read_only_bundler(&config);
+    let mut graph = read_graph(&mut bundler);
+
+    if root.is_some() || depth.is_some() {
+        let root = root.unwrap_or(&config.entry_file);
+
+        graph = match graph.subtree(root, depth) {
+            Some(subtree) => subtree,
+            None => {
+                console::log_error(&format!("Module '{}' is not part of the project", root));
+                process::exit(1);
+            }
+        };
+    }
+
+    if cycles_only {
+        graph = graph.only_cycles();
+    }
+
+    println!("{}", graph.format(format));
+}
+
+/// Prints the shortest import chains pulling a module into the bundle, from the entry file and
+/// the development modules, or only the modules importing it directly
+pub fn explain_module(config: Config, module: &str, importers_only: bool) {
+    let mut bundler = read_only_bundler(&config);
+    let graph = read_graph(&mut bundler);
+
+    if !graph.imports.contains_key(module) {
+        console::log_error(&format!("Module '{}' is not part of the project", module));
+        process::exit(1);
+    }
+
+    // Where a module requires one of its imports, as `file:line`
+    let location = |importer: &str, import: &str| {
+        let file = bundler.module_file(importer).unwrap_or_else(|| importer.to_string());
+        match bundler.visitor().import_line(importer, import) {
+            Some(line) => format!("{}:{}", file, line),
+            None => file,
+        }
+    };
+
+    if importers_only {
+        let importers = graph.importers(module);
+        if importers.is_empty() {
+            println!("Nothing imports '{}'", module);
+        }
+
+        for importer in importers {
+            println!("{} ({})", location(importer, module), importer);
+        }
+
+        return;
+    }
+
+    let roots = bundler.roots();
+    if roots.iter().any(|root| root == module) {
+        println!("'{}' is a root of the bundle", module);
+        return;
+    }
+
+    let chains = graph.shortest_chains(&roots, module);
+    println!("'{}' is pulled in by {} chain(s)", module, chains.len());
+
+    for chain in chains {
+        println!("\n{}", chain.join(" -> "));
+
+        for pair in chain.windows(2) {
+            println!("    {}  requires '{}'", location(&pair[0], &pair[1]), pair[1]);
+        }
+    }
+}
+
+/// Checks every module of the project without writing anything, and prints the problems found.
+/// Exits with an error code if there are errors, or warnings when `strict` is set
+pub fn check_project(config: Config, format: ReportFormat, strict: bool) {
+    let mut bundler = read_only_bundler(&config);
+    let src_dir = bundler.src_dir();
+
+    let (graph, mut diagnostics) = bundler.check();
+    let modules = &graph.modules;
+
+    // Cycles are reported once, on their first module
+    for cycle in graph.cycles() {
+        let chain = graph.cycle_chain(&cycle);
+
+        diagnostics.push(
+            Diagnostic::warning("cycle", format!("Import cycle: {}", chain.join(" -> ")))
+                .with_module(&cycle[0])
+                .with_file(&bundler.module_file(&cycle[0]).unwrap_or_default()),
+        );
+    }
+
+    // Files can only be told unused if every module could be read, in every `--#if` branch so
+    // the modules only another profile requires are used
+    let readable = |diagnostics: &[Diagnostic]| diagnostics.iter().all(|diagnostic| !diagnostic.is_error());
+    let used = match readable(&diagnostics) {
+        true => Some(all_branches_bundler(&config).check()),
+        false => None,
+    };
+
+    if let Some((used, _)) = used.filter(|(_, used_diagnostics)| readable(used_diagnostics)) {
+        match unused::find_unused(&src_dir, &used.modules, &config.unused_ignore) {
+            Ok(files) => {
+                for file in files {
+                    diagnostics.push(
+                        Diagnostic::warning("unused", String::from("No module requires this file"))
+                            .with_file(&format!("{}/{}", src_dir, file)),
+                    );
+                }
+            }
+            Err(err) => diagnostics.push(Diagnostic::error(
+                "io",
+                format!("Could not list the source files: {}", err),
+            )),
+        }
+    }
+
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+
+    match format {
+        ReportFormat::Text => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+
+            println!(
+                "Checked {} module(s): {} error(s), {} warning(s)",
+                modules.len(),
+                errors,
+                warnings
+            );
+        }
+        ReportFormat::Json => {
+            let report = serde_json::json!({
+                "modules": modules.len(),
+                "errors": errors,
+                "warnings": warnings,
+                "diagnostics": diagnostics,
+            });
+
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        }
+    }
+
+    if errors > 0 || (strict && warnings > 0) {
+        process::exit(1);
+    }
+}
+
+/// Prints the module files no module reaches, deleting or archiving them when `fix` is set
+pub fn report_unused(config: Config, fix: bool, archive: Option<&Path>) {
+    // A module required by another profile is still used
+    let mut bundler = all_branches_bundler(&config);
+    let src_dir = bundler.src_dir();
+
+    // A module that can't be read hides the ones it imports, so don't guess
+    let graph = read_graph(&mut bundler);
+    let files = match unused::find_unused(&src_dir, &graph.modules, &config.unused_ignore) {
+        Ok(files) => files,
+        Err(err) => {
+            console::log_error(&format!("Could not list the source files: {}", err));
+            process::exit(1);
+        }
+    };
+
+    if files.is_empty() {
+        println!("Every file is used");
+        return;
+    }
+
+    for file in &files {
+        println!("{}/{}", src_dir, file);
+    }
+
+    if !fix {
+        println!("{} unused file(s), run with --fix to remove them", files.len());
+        return;
+    }
+
+    for file in &files {
+        if let Err(err) = unused::remove_file(&config.src_path(), file, archive) {
+            console::log_error(&format!("Could not remove '{}': {}", file, err));
+            process::exit(1);
+        }
+    }
+
+    match archive {
+        Some(archive) => println!(
+            "Moved {} unused file(s) to '{}'",
+            files.len(),
+            archive.display()
+        ),
+        None => println!("Deleted {} unused file(s)", files.len()),
+    }
 }
@@ -1,65 +1,676 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
-use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fmt, fs, io};
+
+use crate::cache;
+use crate::package::{self, Package, LOCKFILE_NAME};
+use crate::parser;
+use crate::scaffold::Plan;
+use crate::vfs::DiskFs;
 
 // Constants
-const CONFIG_FILE_NAME: &str = ".luajoin.json";
+pub(crate) const CONFIG_FILE_NAME: &str = ".luajoin.json";
 const PROJ_FILE_NAME: &str = ".project.json";
 
-// Files
-const DEV_FILE_CONTENT: &str = include_str!("lua/.dev.lua");
-const MAIN_FILE_CONTENT: &str = include_str!("lua/main.lua");
+/// Keys that are allowed in the project file, without being part of the config
+const IGNORED_KEYS: [&str; 1] = ["$schema"];
+
+/// The JSON Schema of the project file, for editors to validate it
+pub const SCHEMA: &str = include_str!("../luajoin.schema.json");
+
+/// The Lua dialect the bundle is meant to run on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    #[default]
+    Lua51,
+    Luau,
+    Roblox,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Lua51 => write!(f, "lua51"),
+            Target::Luau => write!(f, "luau"),
+            Target::Roblox => write!(f, "roblox"),
+        }
+    }
+}
+
+/// Settings a profile can override, the unset ones are inherited from the project file
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defines: Option<BTreeMap<String, bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minify: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev_files: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_map: Option<bool>,
+}
+
+/// Sizes in bytes the production bundle and its modules may not exceed
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Budgets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<usize>,
+
+    /// Keyed by glob patterns of module names, like `ui/**`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modules: BTreeMap<String, usize>,
+}
+
+impl Budgets {
+    pub fn is_empty(&self) -> bool {
+        self.bundle.is_none() && self.modules.is_empty()
+    }
+}
+
+/// What `serve` watches on top of the source directory, and what it doesn't
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WatchSettings {
+    /// Files or directories, relative to the project's root, whose changes rebuild the whole bundle
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+
+    /// Glob patterns of the paths whose changes are ignored, like `*.tmp` or `/docs/`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+
+    /// Whether the files are polled for changes instead of relying on the system's events, which
+    /// network shares and some mounted drives don't send
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<bool>,
+
+    /// How often the files are polled, in milliseconds (defaults to 1000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+
+    /// How long to wait for more changes before rebuilding, in milliseconds (defaults to 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce: Option<u64>,
+}
+
+impl WatchSettings {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+            && self.ignore.is_empty()
+            && self.poll.is_none()
+            && self.poll_interval.is_none()
+            && self.debounce.is_none()
+    }
+
+    pub fn polls(&self) -> bool {
+        self.poll.unwrap_or(false)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval.unwrap_or(1000).max(1))
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce.unwrap_or(100).max(1))
+    }
+}
+
+/// The settings of the selected profile, with everything inherited filled in
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub profile: String,
+    pub out_dir: String,
+    pub defines: BTreeMap<String, bool>,
+    pub minify: bool,
+    pub target: Target,
+    pub dev_files: Vec<String>,
+    pub source_map: bool,
+}
+
+impl Settings {
+    /// The settings of the built-in `dev` and `prod` profiles
+    fn builtin(profile: &str) -> Option<Self> {
+        let production = match profile {
+            "dev" => false,
+            "prod" => true,
+            _ => return None,
+        };
+
+        Some(Self {
+            profile: profile.to_string(),
+            minify: production,
+            dev_files: vec![String::from(".dev")],
+            source_map: !production,
+            ..Default::default()
+        })
+    }
+
+    /// The defines of the profile, along with the ones of the target (`LUA51`, `LUAU`, `ROBLOX`)
+    pub fn active_defines(&self) -> BTreeMap<String, bool> {
+        let target_defines: &[&str] = match self.target {
+            Target::Lua51 => &["LUA51"],
+            Target::Luau => &["LUAU"],
+            Target::Roblox => &["LUAU", "ROBLOX"],
+        };
+
+        let mut defines: BTreeMap<String, bool> = target_defines
+            .iter()
+            .map(|name| (name.to_string(), true))
+            .collect();
+
+        defines.extend(self.defines.clone());
+        defines
+    }
+
+    /// Whether a source map is written next to the bundle. Minified bundles have no line breaks
+    /// to map, so they never get one
+    pub fn writes_source_map(&self) -> bool {
+        self.source_map && !self.minify
+    }
+
+    /// Overrides every setting the profile sets
+    fn apply(&mut self, profile: &Profile) {
+        if let Some(out_dir) = &profile.out_dir {
+            self.out_dir = out_dir.clone();
+        }
+
+        // Defines are merged, so a profile only has to list the ones it changes
+        if let Some(defines) = &profile.defines {
+            self.defines.extend(defines.clone());
+        }
+
+        if let Some(minify) = profile.minify {
+            self.minify = minify;
+        }
+
+        if let Some(target) = profile.target {
+            self.target = target;
+        }
+
+        if let Some(dev_files) = &profile.dev_files {
+            self.dev_files = dev_files.clone();
+        }
+
+        if let Some(source_map) = profile.source_map {
+            self.source_map = source_map;
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub entry_file: String,
     pub src_dir: String,
     pub out_dir: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defines: Option<BTreeMap<String, bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minify: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev_files: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_map: Option<bool>,
+
+    /// Glob patterns (relative to the source directory) of the files never reported as unused
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unused_ignore: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Budgets::is_empty")]
+    pub budgets: Budgets,
+
+    #[serde(default, skip_serializing_if = "WatchSettings::is_empty")]
+    pub watch: WatchSettings,
+
+    /// Local packages by name, each a directory relative to the project's root. Their modules are
+    /// required as `<name>/<module>`, and the entry of their own project file as `<name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+
+    /// Whether the visited modules are kept in `.luajoin-cache` between runs (defaults to true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+
+    /// Named sets of settings, selected with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// The directory containing the project file, every path is relative to it
+    #[serde(skip)]
+    pub root: PathBuf,
+
+    /// The project file itself
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    /// The settings of the selected profile
+    #[serde(skip)]
+    pub settings: Settings,
+
+    /// The dependencies, resolved
+    #[serde(skip)]
+    pub packages: Vec<Package>,
+
+    /// How many modules are read at once, defaults to the number of CPUs
+    #[serde(skip)]
+    pub jobs: Option<usize>,
 }
 
-pub fn create_config_file(src_dir: &str, out_dir: &str, entry: &str) -> Result<(), Box<dyn Error>> {
-    // Create the JSON
-    let config = Config {
-        src_dir: src_dir.to_string(),
-        out_dir: out_dir.to_string(),
+impl Config {
+    fn new(src_dir: &str, out_dir: &str, entry: &str) -> Self {
+        Self {
+            entry_file: entry.to_string(),
+            src_dir: src_dir.to_string(),
+            out_dir: out_dir.to_string(),
+            defines: None,
+            minify: None,
+            target: None,
+            dev_files: None,
+            source_map: None,
+            unused_ignore: Vec::new(),
+            budgets: Budgets::default(),
+            watch: WatchSettings::default(),
+            dependencies: BTreeMap::new(),
+            cache: None,
+            profiles: BTreeMap::new(),
+            root: PathBuf::new(),
+            path: PathBuf::new(),
+            settings: Settings::default(),
+            packages: Vec::new(),
+            jobs: None,
+        }
+    }
 
-        entry_file: entry.to_string(),
-    };
+    /// The source directory, resolved from the project's root
+    pub fn src_path(&self) -> PathBuf {
+        self.root.join(&self.src_dir)
+    }
 
-    // Create the file
-    let config_file = File::create(CONFIG_FILE_NAME)?;
+    /// The output directory of the selected profile, resolved from the project's root
+    pub fn out_path(&self) -> PathBuf {
+        self.root.join(&self.settings.out_dir)
+    }
 
-    // Write the JSON
-    serde_json::to_writer_pretty(config_file, &config)?;
+    /// The folders Wally installed packages in, next to the project file
+    pub fn wally_packages(&self) -> Vec<Package> {
+        package::wally_packages(&self.root, &self.src_path())
+    }
+
+    /// The lockfile of the dependencies
+    pub fn lock_path(&self) -> PathBuf {
+        self.root.join(LOCKFILE_NAME)
+    }
+
+    /// The cache directory, unless caching is disabled
+    pub fn cache_path(&self) -> Option<PathBuf> {
+        match self.cache {
+            Some(false) => None,
+            _ => Some(self.root.join(cache::CACHE_DIR_NAME)),
+        }
+    }
+
+    /// Selects a profile, layering its settings over the project file's and the built-in ones.
+    /// Profiles that are not built-in start from the default profile's settings. Returns warnings
+    /// about settings the profile ignores
+    pub fn select_profile(
+        &mut self,
+        profile: Option<&str>,
+        default: &str,
+    ) -> Result<Vec<String>, String> {
+        let name = profile.unwrap_or(default);
+
+        if Settings::builtin(name).is_none() && !self.profiles.contains_key(name) {
+            let mut available = BTreeSet::from(["dev", "prod"]);
+            available.extend(self.profiles.keys().map(String::as_str));
+
+            return Err(format!(
+                "Unknown profile '{}', expected one of {}",
+                name,
+                Vec::from_iter(available).join(", ")
+            ));
+        }
+
+        let mut settings = Settings::builtin(name)
+            .or_else(|| Settings::builtin(default))
+            .unwrap_or_default();
+
+        settings.profile = name.to_string();
+        settings.apply(&Profile {
+            out_dir: Some(self.out_dir.clone()),
+            defines: self.defines.clone(),
+            minify: self.minify,
+            target: self.target,
+            dev_files: self.dev_files.clone(),
+            source_map: self.source_map,
+        });
+
+        let mut source_map = self.source_map;
+        if let Some(profile) = self.profiles.get(name) {
+            settings.apply(profile);
+            source_map = profile.source_map.or(source_map);
+        }
+
+        let mut warnings = Vec::new();
+        if source_map == Some(true) && settings.minify {
+            warnings.push(format!(
+                "Profile '{}' minifies the bundle, so its source map is not written",
+                name
+            ));
+        }
+
+        self.settings = settings;
+        Ok(warnings)
+    }
+}
+
+/// Plans the creation of the project, writing the given files (relative to the source directory)
+/// into it. Existing files are kept, and missing `.gitignore` lines are appended
+pub fn plan_project(
+    src_dir: &str,
+    out_dir: &str,
+    entry: &str,
+    files: &[(String, String)],
+) -> Result<Plan, Box<dyn Error>> {
+    // Create the JSON
+    let config = Config::new(src_dir, out_dir, entry);
+
+    // An existing source directory is adopted, but it has to be a directory
+    for dir in [&config.src_dir, &config.out_dir] {
+        let path = Path::new(dir);
+        if path.exists() && !path.is_dir() {
+            return Err(format!("'{}' exists but is not a directory", dir).into());
+        }
+    }
+
+    let mut plan = Plan::new();
+    plan.create_file(
+        Path::new(CONFIG_FILE_NAME),
+        serde_json::to_string_pretty(&config)?,
+    );
 
     // Create the source and output directories
-    fs::create_dir(&config.src_dir)?;
-    fs::create_dir(&config.out_dir)?;
+    plan.create_dir(Path::new(&config.src_dir));
+    plan.create_dir(Path::new(&config.out_dir));
 
-    // Write the entry file
-    let entry_path = format!("{}/{}.lua", &config.src_dir, &config.entry_file);
+    // Write the template's files
+    for (path, content) in files {
+        plan.create_file(&Path::new(&config.src_dir).join(path), content.clone());
+    }
 
-    // Write the dev file
-    let dev_path = format!("{}/.dev.lua", &config.src_dir);
+    let gitignore_lines = vec![
+        format!("/{}", &config.out_dir),
+        format!("/{}/.dev.lua", &config.src_dir),
+        format!("/{}", cache::CACHE_DIR_NAME),
+    ];
 
-    let gitignore_content = format!("/{}\n/{}/.dev.lua", &config.out_dir, &config.src_dir);
+    plan.ensure_lines(Path::new(".gitignore"), &gitignore_lines)?;
+    plan.create_file(
+        Path::new(PROJ_FILE_NAME),
+        serde_json::json!({ "tree": { "$path": &config.src_dir } }).to_string(),
+    );
 
-    fs::write(entry_path, MAIN_FILE_CONTENT)?;
-    fs::write(dev_path, DEV_FILE_CONTENT)?;
-    fs::write(".gitignore", &gitignore_content)?;
-    fs::write(PROJ_FILE_NAME, format!("{{\"tree\":{{\"$path\":\"src\"}}}}"))?;
+    Ok(plan)
+}
 
-    Ok(())
+/// Everything that can go wrong when loading the project file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The project file does not exist
+    Missing(PathBuf),
+    /// The project file exists, but could not be read
+    Io(PathBuf, io::Error),
+    /// The project file is not valid JSON, or a field has the wrong type
+    Invalid {
+        path: PathBuf,
+        field: Option<String>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The project file is valid, but a field points at something that does not exist
+    Validation {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
 }
 
-pub fn get_config() -> Option<Config> {
-    let file = match File::open(CONFIG_FILE_NAME) {
-        Ok(res) => res,
-        Err(_) => return None,
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(path) => write!(
+                f,
+                "Project file '{}' not found in this directory or its parents, run `luajoin init` to create one",
+                path.display()
+            ),
+            ConfigError::Io(path, err) => write!(f, "Could not read '{}': {}", path.display(), err),
+            ConfigError::Invalid {
+                path,
+                field,
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)?;
+                match field {
+                    Some(field) => write!(f, " (in '{}')", field),
+                    None => Ok(()),
+                }
+            }
+            ConfigError::Validation {
+                path,
+                field,
+                message,
+            } => write!(f, "{}: '{}' {}", path.display(), field, message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Whether there is a project file in the current directory
+pub fn config_exists() -> bool {
+    Path::new(CONFIG_FILE_NAME).exists()
+}
+
+/// Parses the content of a project file, returning the config and a warning for every unknown key
+pub fn parse_config(content: &str, path: &Path) -> Result<(Config, Vec<String>), ConfigError> {
+    let mut unknown_keys = Vec::new();
+    let mut track_unknown = |key: serde_ignored::Path| unknown_keys.push(key.to_string());
+    let mut deserializer = serde_json::Deserializer::from_str(content);
+
+    let result = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+        &mut deserializer,
+        &mut track_unknown,
+    ))
+    .map_err(|err| (Some(err.path().to_string()), err.into_inner()))
+    .and_then(|config| {
+        deserializer
+            .end()
+            .map(|_| config)
+            .map_err(|err| (None, err))
+    });
+
+    let config: Config = result.map_err(|(field, err)| {
+        // The position is already stored separately, only keep the message itself
+        let position = format!(" at line {} column {}", err.line(), err.column());
+        let message = err.to_string();
+
+        ConfigError::Invalid {
+            path: path.to_path_buf(),
+            field: field.filter(|field| err.is_data() && field != "."),
+            line: err.line(),
+            column: err.column(),
+            message: message.strip_suffix(&position).unwrap_or(&message).to_string(),
+        }
+    })?;
+
+    let warnings = unknown_keys
+        .into_iter()
+        .filter(|key| !IGNORED_KEYS.contains(&key.as_str()))
+        .map(|key| format!("Unknown key '{}' in '{}'", key, path.display()))
+        .collect();
+
+    Ok((config, warnings))
+}
+
+/// Makes sure the paths in the config point at existing files. The source directory and entry are
+/// only looked for when `check_entry` is set, as the modules may not be read from the disk
+fn validate_config(config: &Config, path: &Path, check_entry: bool) -> Result<(), ConfigError> {
+    let invalid = |field: &str, message: String| ConfigError::Validation {
+        path: path.to_path_buf(),
+        field: field.to_string(),
+        message,
     };
 
-    match serde_json::from_reader(file) {
-        Ok(res) => res,
-        Err(_) => None,
+    if !check_entry {
+        return Ok(());
+    }
+
+    let src_path = config.src_path();
+    if !src_path.is_dir() {
+        return Err(invalid(
+            "src_dir",
+            format!("is not a directory: '{}'", src_path.display()),
+        ));
+    }
+
+    if !parser::module_exists(&DiskFs, &src_path.to_string_lossy(), &config.entry_file) {
+        return Err(invalid(
+            "entry_file",
+            format!(
+                "does not match any module in '{}': '{}'",
+                src_path.display(),
+                config.entry_file
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Resolves the dependencies, warning about the ones named like a module of the source directory
+fn load_packages(config: &Config, path: &Path) -> Result<(Vec<Package>, Vec<String>), ConfigError> {
+    let mut packages = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (name, dependency) in &config.dependencies {
+        let package = Package::load(name, dependency, &config.root).map_err(|message| {
+            ConfigError::Validation {
+                path: path.to_path_buf(),
+                field: format!("dependencies.{}", name),
+                message,
+            }
+        })?;
+
+        if parser::module_exists(&DiskFs, &config.src_path().to_string_lossy(), name) {
+            warnings.push(format!(
+                "Dependency '{}' shadows the module of the same name in '{}'",
+                name, config.src_dir
+            ));
+        }
+
+        packages.push(package);
+    }
+
+    Ok((packages, warnings))
+}
+
+/// Finds the project file, either the given one or the closest one in the current directory or
+/// its parents. The returned path is relative to the current directory whenever possible
+pub fn find_config(explicit: Option<&Path>) -> Result<PathBuf, ConfigError> {
+    if let Some(path) = explicit {
+        if !path.is_file() {
+            return Err(ConfigError::Missing(path.to_path_buf()));
+        }
+
+        return Ok(path.to_path_buf());
+    }
+
+    let cur_dir = env::current_dir().map_err(|err| ConfigError::Io(PathBuf::from("."), err))?;
+
+    for (depth, dir) in cur_dir.ancestors().enumerate() {
+        if dir.join(CONFIG_FILE_NAME).is_file() {
+            let mut path: PathBuf = (0..depth).map(|_| "..").collect();
+            path.push(CONFIG_FILE_NAME);
+
+            return Ok(path);
+        }
+    }
+
+    Err(ConfigError::Missing(PathBuf::from(CONFIG_FILE_NAME)))
+}
+
+/// Loads and validates the project file, returning the config and any warnings about it. Without
+/// `check_entry`, the source directory and entry don't have to exist on the disk
+pub fn load_config(
+    explicit: Option<&Path>,
+    check_entry: bool,
+) -> Result<(Config, Vec<String>), ConfigError> {
+    let path = find_config(explicit)?;
+
+    let content = fs::read_to_string(&path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => ConfigError::Missing(path.clone()),
+        _ => ConfigError::Io(path.clone(), err),
+    })?;
+
+    let (mut config, warnings) = parse_config(&content, &path)?;
+    config.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    config.path = path.clone();
+
+    validate_config(&config, &path, check_entry)?;
+
+    let (packages, package_warnings) = load_packages(&config, &path)?;
+    config.packages = packages;
+
+    Ok((config, [warnings, package_warnings].concat()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_about_unknown_keys() {
+        let content = r#"{ "$schema": "x", "entry_file": "main", "src_dir": "src", "out_dir": "out", "srcdir": 1 }"#;
+        let (_, warnings) = parse_config(content, Path::new(CONFIG_FILE_NAME)).unwrap();
+
+        assert_eq!(warnings, vec!["Unknown key 'srcdir' in '.luajoin.json'"]);
+    }
+
+    #[test]
+    fn reports_invalid_field() {
+        let content = "{\n  \"entry_file\": \"main\",\n  \"src_dir\": 3,\n  \"out_dir\": \"out\"\n}";
+
+        match parse_config(content, Path::new(CONFIG_FILE_NAME)) {
+            Err(ConfigError::Invalid {
+                field, line, message, ..
+            }) => {
+                assert_eq!(field.as_deref(), Some("src_dir"));
+                assert_eq!(line, 3);
+                assert_eq!(message, "invalid type: integer `3`, expected a string");
+            }
+            _ => panic!("expected an invalid config error"),
+        }
+    }
+
+    #[test]
+    fn warns_about_source_maps_of_minified_profiles() {
+        let content = r#"{ "entry_file": "main", "src_dir": "src", "out_dir": "out", "source_map": true }"#;
+        let (mut config, _) = parse_config(content, Path::new(CONFIG_FILE_NAME)).unwrap();
+
+        assert_eq!(config.select_profile(Some("dev"), "dev"), Ok(vec![]));
+        let warning = "Profile 'prod' minifies the bundle, so its source map is not written";
+        assert_eq!(config.select_profile(Some("prod"), "dev"), Ok(vec![warning.to_string()]));
+        assert!(!config.settings.writes_source_map());
     }
 }
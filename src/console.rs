@@ -1,7 +1,16 @@
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use colorize::AnsiColor;
 
+// Set when stdout is only for what a command prints, like a JSON report
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends the next logs to stderr, without the prompt, leaving stdout to `print`
+pub fn log_to_stderr() {
+    LOG_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
 pub fn clear() {
     print!("\x1B[2J\x1B[1;1H");
     std::io::stdout().flush().unwrap();
@@ -19,7 +28,25 @@ pub fn log_error(text: &str) {
     );
 }
 
+/// Logs to stderr, with yellow text, so it doesn't mix with what a command prints
+pub fn log_warning(text: &str) {
+    let cur_time = chrono::Local::now().format("%H:%M:%S").to_string();
+
+    eprintln!(
+        "{} {} | {}",
+        cur_time.black(),
+        "LuaJoin".yellow(),
+        text.to_string().yellow()
+    );
+}
+
 pub fn log(text: &str) {
+    if LOG_TO_STDERR.load(Ordering::Relaxed) {
+        let cur_time = chrono::Local::now().format("%H:%M:%S").to_string();
+        eprintln!("{} {} | {}", cur_time.black(), "LuaJoin".yellow(), text);
+        return;
+    }
+
     // clear the current line
     print!("\x1B[2K\r");
     std::io::stdout().flush().unwrap();
@@ -31,6 +58,14 @@ pub fn log(text: &str) {
     std::io::stdout().flush().unwrap();
 }
 
+/// Prints text as is, in place of the prompt
+pub fn print(text: &str) {
+    if !LOG_TO_STDERR.load(Ordering::Relaxed) {
+        print!("\x1B[2K\r");
+    }
+    println!("{}", text);
+}
+
 pub fn log_inline(text: &str) {
     // clear the current line
     print!("\x1B[2K\r");
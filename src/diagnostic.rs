@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// How many lines are shown before and after the line of a code frame
+const FRAME_CONTEXT: usize = 2;
+
+/// A problem found in the project, and where it is
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str, // a short name for the kind of problem, like `unresolved`
+    pub module: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            module: None,
+            file: None,
+            line: None,
+            message,
+        }
+    }
+
+    pub fn warning(code: &'static str, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    pub fn with_module(mut self, module: &str) -> Self {
+        self.module = Some(module.to_string());
+        self
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    pub fn with_line(mut self, line: Option<usize>) -> Self {
+        self.line = line;
+        self
+    }
+
+    /// The lines around the line of the diagnostic in the source of its file, the line itself
+    /// marked with `>`
+    pub fn code_frame(&self, source: &str) -> Option<String> {
+        let line = self.line.filter(|line| *line > 0)?;
+
+        let lines: Vec<&str> = source.lines().collect();
+        if line > lines.len() {
+            return None;
+        }
+
+        let first = line.saturating_sub(FRAME_CONTEXT + 1);
+        let last = (line + FRAME_CONTEXT).min(lines.len());
+        let width = last.to_string().len();
+
+        let frame: Vec<String> = (first..last)
+            .map(|i| {
+                let marker = if i + 1 == line { '>' } else { ' ' };
+                format!("{} {:>width$} | {}", marker, i + 1, lines[i], width = width)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+
+        Some(frame.join("\n"))
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Point at the most precise location known
+        match (&self.file, self.line, &self.module) {
+            (Some(file), Some(line), _) => write!(f, "{}:{}: ", file, line)?,
+            (Some(file), None, _) => write!(f, "{}: ", file)?,
+            (None, _, Some(module)) => write!(f, "'{}': ", module)?,
+            (None, _, None) => (),
+        }
+
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_the_line() {
+        let source = "local a = 1\nlocal b = require(\"./b\")\n\nreturn a";
+        let diagnostic = Diagnostic::error("unresolved", String::from("missing"));
+
+        assert_eq!(diagnostic.code_frame(source), None);
+        assert_eq!(
+            diagnostic.with_line(Some(2)).code_frame(source).unwrap(),
+            "  1 | local a = 1\n> 2 | local b = require(\"./b\")\n  3 |\n  4 | return a"
+        );
+    }
+}
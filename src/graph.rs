@@ -0,0 +1,469 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use clap::ValueEnum;
+use serde_json::json;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+    Mermaid,
+}
+
+/// The modules of a project, and the modules each of them imports
+pub struct ModuleGraph {
+    pub modules: Vec<String>, // in the order they were found
+    pub imports: HashMap<String, Vec<String>>,
+    importers: HashMap<String, Vec<String>>,
+}
+
+impl ModuleGraph {
+    pub fn new(modules: Vec<String>, imports: &HashMap<String, Vec<String>>) -> Self {
+        // JSON modules import nothing, so they have no entry in the memo
+        let imports = modules
+            .iter()
+            .map(|module| {
+                let module_imports = imports.get(module).cloned().unwrap_or_default();
+                (module.clone(), module_imports)
+            })
+            .collect();
+
+        Self::with_imports(modules, imports)
+    }
+
+    /// Indexes the importers of each module, in the order they were found
+    fn with_imports(modules: Vec<String>, imports: HashMap<String, Vec<String>>) -> Self {
+        let mut importers: HashMap<String, Vec<String>> = HashMap::new();
+        for module in &modules {
+            // A module requiring another twice is still a single importer of it
+            let module_imports: HashSet<&String> = imports[module].iter().collect();
+            for import in module_imports {
+                importers.entry(import.clone()).or_default().push(module.clone());
+            }
+        }
+
+        Self {
+            modules,
+            imports,
+            importers,
+        }
+    }
+
+    fn imports_of(&self, module: &str) -> &[String] {
+        self.imports.get(module).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Keeps the modules reachable from the root, at most `depth` imports away from it
+    pub fn subtree(&self, root: &str, depth: Option<usize>) -> Option<Self> {
+        if !self.imports.contains_key(root) {
+            return None;
+        }
+
+        let mut modules = vec![root.to_string()];
+        let mut queue = VecDeque::from([(root.to_string(), 0)]);
+        let mut seen = HashSet::from([root.to_string()]);
+
+        while let Some((module, distance)) = queue.pop_front() {
+            if depth.is_some_and(|depth| distance >= depth) {
+                continue;
+            }
+
+            for import in self.imports_of(&module) {
+                if seen.insert(import.clone()) {
+                    modules.push(import.clone());
+                    queue.push_back((import.clone(), distance + 1));
+                }
+            }
+        }
+
+        // Drop the edges going out of the subtree
+        let imports = modules
+            .iter()
+            .map(|module| {
+                let module_imports = self
+                    .imports_of(module)
+                    .iter()
+                    .filter(|import| seen.contains(*import))
+                    .cloned()
+                    .collect();
+
+                (module.clone(), module_imports)
+            })
+            .collect();
+
+        Some(Self::with_imports(modules, imports))
+    }
+
+    /// The modules importing the given one directly, in the order they were found
+    pub fn importers(&self, module: &str) -> Vec<&String> {
+        self.importers.get(module).map(|importers| importers.iter().collect()).unwrap_or_default()
+    }
+
+    /// Every shortest import chain from each of the roots to the module, starting with the root
+    pub fn shortest_chains(&self, roots: &[String], module: &str) -> Vec<Vec<String>> {
+        // Walk the imports backwards, to know how far each module is from the searched one
+        let mut distances = HashMap::from([(module, 0)]);
+        let mut queue = VecDeque::from([module]);
+
+        while let Some(cur) = queue.pop_front() {
+            for importer in self.importers.get(cur).into_iter().flatten() {
+                if !distances.contains_key(importer.as_str()) {
+                    distances.insert(importer, distances[cur] + 1);
+                    queue.push_back(importer);
+                }
+            }
+        }
+
+        let mut chains = Vec::new();
+        for root in roots {
+            if distances.contains_key(root.as_str()) {
+                self.collect_chains(vec![root.clone()], &distances, &mut chains);
+            }
+        }
+
+        chains
+    }
+
+    fn collect_chains(
+        &self,
+        chain: Vec<String>,
+        distances: &HashMap<&str, usize>,
+        chains: &mut Vec<Vec<String>>,
+    ) {
+        let last = chain.last().unwrap();
+        let distance = distances[last.as_str()];
+
+        if distance == 0 {
+            chains.push(chain);
+            return;
+        }
+
+        // Only follow the imports getting one step closer
+        for import in self.imports_of(last) {
+            if distances.get(import.as_str()) == Some(&(distance - 1)) {
+                let mut next = chain.clone();
+                next.push(import.clone());
+                self.collect_chains(next, distances, chains);
+            }
+        }
+    }
+
+    /// Groups of modules that import each other, directly or not (Tarjan's algorithm), each in
+    /// the order the modules were found. A module importing itself is a cycle of one module
+    pub fn cycles(&self) -> Vec<Vec<String>> {
+        let mut state = TarjanState::default();
+
+        for module in &self.modules {
+            if !state.indices.contains_key(module.as_str()) {
+                self.strong_connect(module, &mut state);
+            }
+        }
+
+        let order: HashMap<&String, usize> = self
+            .modules
+            .iter()
+            .enumerate()
+            .map(|(i, module)| (module, i))
+            .collect();
+
+        let mut cycles: Vec<Vec<String>> = state
+            .components
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.imports_of(&component[0]).contains(&component[0])
+            })
+            .collect();
+
+        for cycle in &mut cycles {
+            cycle.sort_by_key(|module| order[module]);
+        }
+
+        cycles.sort_by_key(|cycle| order[&cycle[0]]);
+        cycles
+    }
+
+    /// The shortest chain of imports going from the first module of a cycle back to it
+    pub fn cycle_chain(&self, cycle: &[String]) -> Vec<String> {
+        let start = &cycle[0];
+        let mut previous: HashMap<&String, &String> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(module) = queue.pop_front() {
+            for import in self.imports_of(module) {
+                if import == start {
+                    // Walk back to the start to get the chain
+                    let mut chain = vec![start.clone(), module.clone()];
+                    let mut cur = module;
+                    while let Some(prev) = previous.get(cur) {
+                        chain.push((*prev).clone());
+                        cur = prev;
+                    }
+
+                    chain.reverse();
+                    return chain;
+                }
+
+                if cycle.contains(import) && !previous.contains_key(import) {
+                    previous.insert(import, module);
+                    queue.push_back(import);
+                }
+            }
+        }
+
+        vec![start.clone()]
+    }
+
+    fn strong_connect<'a>(&'a self, module: &'a str, state: &mut TarjanState<'a>) {
+        let index = state.indices.len();
+        state.indices.insert(module, index);
+        state.low_links.insert(module, index);
+        state.stack.push(module);
+        state.on_stack.insert(module);
+
+        for import in self.imports_of(module) {
+            if !state.indices.contains_key(import.as_str()) {
+                self.strong_connect(import, state);
+                let low_link = state.low_links[import.as_str()].min(state.low_links[module]);
+                state.low_links.insert(module, low_link);
+            } else if state.on_stack.contains(import.as_str()) {
+                let low_link = state.indices[import.as_str()].min(state.low_links[module]);
+                state.low_links.insert(module, low_link);
+            }
+        }
+
+        // The module is the root of a component, pop it from the stack
+        if state.low_links[module] == index {
+            let mut component = Vec::new();
+
+            while let Some(member) = state.stack.pop() {
+                state.on_stack.remove(member);
+                component.push(member.to_string());
+
+                if member == module {
+                    break;
+                }
+            }
+
+            component.reverse();
+            state.components.push(component);
+        }
+    }
+
+    /// The imports that are part of a cycle
+    pub fn cycle_edges(&self) -> HashSet<(String, String)> {
+        let mut edges = HashSet::new();
+
+        for cycle in self.cycles() {
+            let members: HashSet<&String> = cycle.iter().collect();
+
+            for module in &cycle {
+                for import in self.imports_of(module) {
+                    if members.contains(import) {
+                        edges.insert((module.clone(), import.clone()));
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Keeps the modules that are part of a cycle
+    pub fn only_cycles(&self) -> Self {
+        let members: BTreeSet<String> = self.cycles().into_iter().flatten().collect();
+        let cycle_edges = self.cycle_edges();
+
+        let modules: Vec<String> = self
+            .modules
+            .iter()
+            .filter(|module| members.contains(*module))
+            .cloned()
+            .collect();
+
+        let imports = modules
+            .iter()
+            .map(|module| {
+                let module_imports = self
+                    .imports_of(module)
+                    .iter()
+                    .filter(|import| cycle_edges.contains(&(module.clone(), (*import).clone())))
+                    .cloned()
+                    .collect();
+
+                (module.clone(), module_imports)
+            })
+            .collect();
+
+        Self::with_imports(modules, imports)
+    }
+
+    /// Every import, in the order the modules were found
+    fn edges(&self) -> Vec<(&String, &String)> {
+        self.modules
+            .iter()
+            .flat_map(|module| {
+                self.imports_of(module)
+                    .iter()
+                    .map(move |import| (module, import))
+            })
+            .collect()
+    }
+
+    pub fn format(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Json => self.to_json(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    /// Formats the graph for Graphviz, with the imports part of a cycle in red
+    pub fn to_dot(&self) -> String {
+        let cycle_edges = self.cycle_edges();
+        let mut lines = vec![String::from("digraph luajoin {")];
+
+        for module in &self.modules {
+            lines.push(format!("    {:?};", module));
+        }
+
+        for (module, import) in self.edges() {
+            let style = if cycle_edges.contains(&(module.clone(), import.clone())) {
+                " [color=red]"
+            } else {
+                ""
+            };
+
+            lines.push(format!("    {:?} -> {:?}{};", module, import, style));
+        }
+
+        lines.push(String::from("}"));
+        lines.join("\n")
+    }
+
+    /// Formats the graph as a Mermaid flowchart, with the imports part of a cycle in red
+    pub fn to_mermaid(&self) -> String {
+        let cycle_edges = self.cycle_edges();
+        let ids: HashMap<&String, usize> = self
+            .modules
+            .iter()
+            .enumerate()
+            .map(|(i, module)| (module, i))
+            .collect();
+
+        let mut lines = vec![String::from("flowchart LR")];
+
+        // Module names can contain characters Mermaid doesn't allow in ids
+        for (i, module) in self.modules.iter().enumerate() {
+            lines.push(format!("    m{}[\"{}\"]", i, module.replace('"', "#quot;")));
+        }
+
+        let mut cycle_links = Vec::new();
+        for (i, (module, import)) in self.edges().into_iter().enumerate() {
+            lines.push(format!("    m{} --> m{}", ids[module], ids[import]));
+
+            if cycle_edges.contains(&(module.clone(), import.clone())) {
+                cycle_links.push(i.to_string());
+            }
+        }
+
+        if !cycle_links.is_empty() {
+            lines.push(format!("    linkStyle {} stroke:red", cycle_links.join(",")));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Formats the graph as JSON, listing the modules, their imports and the cycles
+    pub fn to_json(&self) -> String {
+        let imports: serde_json::Map<String, serde_json::Value> = self
+            .modules
+            .iter()
+            .map(|module| (module.clone(), json!(self.imports_of(module))))
+            .collect();
+
+        let value = json!({
+            "modules": self.modules,
+            "imports": imports,
+            "cycles": self.cycles(),
+        });
+
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+#[derive(Default)]
+struct TarjanState<'a> {
+    indices: HashMap<&'a str, usize>,
+    low_links: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: HashSet<&'a str>,
+    components: Vec<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> ModuleGraph {
+        let modules = edges.iter().map(|(module, _)| module.to_string()).collect();
+        let imports = edges
+            .iter()
+            .map(|(module, imports)| {
+                let imports = imports.iter().map(|import| import.to_string()).collect();
+                (module.to_string(), imports)
+            })
+            .collect();
+
+        ModuleGraph::new(modules, &imports)
+    }
+
+    #[test]
+    fn finds_cycles() {
+        let graph = graph(&[
+            ("main", &["a", "c"]),
+            ("a", &["b"]),
+            ("b", &["a"]),
+            ("c", &["c"]),
+        ]);
+
+        assert_eq!(graph.cycles(), vec![vec!["a", "b"], vec!["c"]]);
+        assert!(graph.cycle_edges().contains(&("b".to_string(), "a".to_string())));
+        assert!(!graph.cycle_edges().contains(&("main".to_string(), "a".to_string())));
+        assert_eq!(graph.cycle_chain(&graph.cycles()[0]), vec!["a", "b", "a"]);
+        assert_eq!(graph.cycle_chain(&graph.cycles()[1]), vec!["c", "c"]);
+    }
+
+    #[test]
+    fn finds_every_shortest_chain() {
+        let graph = graph(&[
+            ("main", &["a", "b", "c"]),
+            ("a", &["util"]),
+            ("b", &["util"]),
+            ("c", &["d"]),
+            ("d", &["util"]),
+            (".dev", &["util"]),
+            ("util", &[]),
+        ]);
+
+        let roots = vec![String::from("main"), String::from(".dev")];
+        assert_eq!(
+            graph.shortest_chains(&roots, "util"),
+            vec![
+                vec!["main", "a", "util"],
+                vec!["main", "b", "util"],
+                vec![".dev", "util"],
+            ]
+        );
+        assert_eq!(graph.importers("util"), vec!["a", "b", "d", ".dev"]);
+    }
+
+    #[test]
+    fn limits_subtree_depth() {
+        let graph = graph(&[("main", &["a"]), ("a", &["b"]), ("b", &[])]);
+        let subtree = graph.subtree("a", Some(0)).unwrap();
+
+        assert_eq!(subtree.modules, vec!["a"]);
+        assert!(subtree.imports["a"].is_empty());
+        assert_eq!(graph.subtree("main", Some(1)).unwrap().modules, vec!["main", "a"]);
+    }
+}
@@ -0,0 +1,31 @@
+//! Bundles a Lua project, made of modules requiring each other, into a single file.
+//!
+//! ```no_run
+//! use luajoin::{BundleOptions, Bundler};
+//!
+//! let mut bundler = Bundler::new(BundleOptions::new("src", "main"));
+//! let output = bundler.bundle().unwrap();
+//! std::fs::write("bundle.lua", output.code).unwrap();
+//! ```
+
+mod build;
+mod bundler;
+mod cache;
+pub mod config;
+pub mod diagnostic;
+pub mod graph;
+pub mod package;
+pub mod parser;
+mod path;
+mod preprocess;
+pub mod scaffold;
+pub mod size;
+pub mod template;
+pub mod unused;
+pub mod vfs;
+pub mod watch;
+
+pub use bundler::{BundleError, BundleOptions, BundleOutput, Bundler, SourceMap};
+pub use config::{Config, ConfigError};
+pub use diagnostic::{Diagnostic, Severity};
+pub use vfs::{DiskFs, FileSystem, MemoryFs};
@@ -0,0 +1,14 @@
+--[=[
+    {{name}}
+
+    The value returned by this module is returned by the bundle,
+    so it can be loaded with `loadstring(bundle)()`.
+]=]
+
+local module = {}
+
+function module.greet(name)
+    return "Hello, " .. name .. "!"
+end
+
+return module
\ No newline at end of file
@@ -0,0 +1,3 @@
+local platform = _require("./platform")
+
+platform.print("Hello from " .. platform.name .. "!")
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Picks the implementation for the platform the bundle is running on
+if game ~= nil then
+    return _require("./roblox")
+end
+
+return _require("./lua")
\ No newline at end of file
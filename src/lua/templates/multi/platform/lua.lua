@@ -0,0 +1,8 @@
+local platform = {}
+platform.name = _VERSION
+
+function platform.print(...)
+    io.write("[{{name}}] ", table.concat({ ... }, " "), "\n")
+end
+
+return platform
\ No newline at end of file
@@ -0,0 +1,8 @@
+local platform = {}
+platform.name = "Roblox"
+
+function platform.print(...)
+    print("[{{name}}]", ...)
+end
+
+return platform
\ No newline at end of file
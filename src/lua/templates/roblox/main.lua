@@ -0,0 +1,4 @@
+local Players = game:GetService("Players")
+
+local player = Players.LocalPlayer
+print("Hello, " .. player.Name .. "!")
\ No newline at end of file
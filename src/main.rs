@@ -1,111 +1,474 @@
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use colorize::AnsiColor;
+use std::collections::HashMap;
+use std::env;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, RwLock};
+
+use luajoin::{config, graph, template};
 
 mod cli;
-mod config;
 mod console;
-mod parser;
-mod path;
-mod build;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    /// init, serve, or build
-    #[arg(default_value = "build")]
-    action: String,
-
-    /// Whether to minify the output or not (only for build)
-    #[arg(long, default_value = "false")]
-    minify: bool,
+struct Cli {
+    /// Path to the project file, instead of searching the current directory and its parents
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Run as if luajoin was started in this directory
+    #[arg(long, global = true)]
+    cwd: Option<PathBuf>,
+
+    /// Parse every module again, instead of reusing the ones cached by previous runs
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// How many modules are parsed at once (defaults to the number of CPUs)
+    #[arg(short, long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a new project in the current directory
+    Init(InitArgs),
 
-    match args.action.as_str() {
-        "init" => {
-            console::clear();
-            console::log(&"Initializing Project...".blue());
+    /// Watch the source directory and rebuild the development bundle on changes
+    Serve(ServeArgs),
 
-            // Check if the config file already exists
-            if let Some(_) = config::get_config() {
-                console::log(&"Project file already exists".red());
-                return;
-            }
+    /// Build an optimized bundle for deployment (default)
+    Build(BuildArgs),
+
+    /// Check the project for problems without writing anything
+    Check(CheckArgs),
+
+    /// List the source files no module requires
+    Unused(UnusedArgs),
+
+    /// Print the module dependency graph to stdout
+    Graph(GraphArgs),
+
+    /// Explain which import chains pull a module into the bundle
+    Why(WhyArgs),
+
+    /// Record the dependencies in `luajoin.lock`, for the builds to check they didn't change
+    Lock,
+
+    /// Print the JSON Schema of the project file to stdout
+    Schema,
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// The shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Args, Debug)]
+struct InitArgs {
+    /// The source directory
+    #[arg(long)]
+    src: Option<String>,
+
+    /// The output directory
+    #[arg(long)]
+    out: Option<String>,
+
+    /// The entry module, relative to the source directory and without extension
+    #[arg(long)]
+    entry: Option<String>,
+
+    /// A built-in template (script, roblox, library, multi) or the path to a template directory
+    #[arg(short, long, default_value = template::BUILTIN_TEMPLATES[0])]
+    template: String,
+
+    /// The project name, available to templates as `{{name}}` (defaults to the directory name)
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Do not prompt, use the defaults for anything not given as a flag
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// The profile to use (defaults to `dev`)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// The port the websocket server listens on
+    #[arg(short, long, default_value_t = 1338)]
+    port: u16,
+
+    /// Poll the files for changes, for network shares and mounted drives that don't report them
+    #[arg(long)]
+    poll: bool,
+
+    /// How often the files are polled, in milliseconds (defaults to 1000)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    poll_interval: Option<u64>,
 
-            let stdin = io::stdin();
+    /// How long to wait for more changes before rebuilding, in milliseconds (defaults to 100)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    debounce: Option<u64>,
+}
+
+impl ServeArgs {
+    /// The flags take precedence over the project file
+    fn apply(&self, config: &mut config::Config) {
+        if self.poll {
+            config.watch.poll = Some(true);
+        }
+
+        config.watch.poll_interval = self.poll_interval.or(config.watch.poll_interval);
+        config.watch.debounce = self.debounce.or(config.watch.debounce);
+    }
+}
+
+#[derive(Args, Debug, Default)]
+struct BuildArgs {
+    /// The profile to use (defaults to `prod`)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Skip the optimization pass (whitespace and comment stripping)
+    #[arg(long)]
+    no_minify: bool,
+
+    /// Print the size of each module, as a table (default) or JSON
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "text")]
+    report: Option<cli::ReportFormat>,
+
+    /// Compare the sizes with the previous build's
+    #[arg(long)]
+    diff: bool,
+
+    /// Bundle the files given on stdin as a JSON object of paths to sources, and print the bundle
+    #[arg(long, conflicts_with_all = ["report", "diff"])]
+    stdin: bool,
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t)]
+    format: cli::ReportFormat,
+
+    /// Also fail when there are warnings
+    #[arg(long)]
+    strict: bool,
+
+    /// The profile whose defines are used (defaults to `dev`)
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct UnusedArgs {
+    /// Delete the unused files
+    #[arg(long)]
+    fix: bool,
+
+    /// Move the unused files to this directory instead of deleting them
+    #[arg(long, requires = "fix")]
+    archive: Option<PathBuf>,
+
+    /// The profile whose defines are used (defaults to `dev`)
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct GraphArgs {
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = graph::GraphFormat::Dot)]
+    format: graph::GraphFormat,
+
+    /// Only show the modules imported by this one, directly or not
+    #[arg(long)]
+    root: Option<String>,
 
-            // Ask for source directory
-            let mut src_dir = String::new();
-            console::log_inline(&"Enter the source directory (src): ".magenta());
+    /// Only show the modules at most this many imports away from the root (or the entry file)
+    #[arg(long)]
+    depth: Option<usize>,
 
-            if let Err(err) = stdin.read_line(&mut src_dir) {
-                console::log_error(&format!("Error reading source dir: {}", err));
-                return;
-            };
+    /// Only show the modules and imports that are part of a cycle
+    #[arg(long)]
+    cycles: bool,
 
-            // Ask for output directory
-            let mut out_dir = String::new();
-            console::log_inline(&"Enter the output directory (out): ".magenta());
+    /// The profile whose defines are used (defaults to `dev`)
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct WhyArgs {
+    /// The module, relative to the source directory and without extension
+    module: String,
 
-            if let Err(err) = stdin.read_line(&mut out_dir) {
-                console::log_error(&format!("Error reading output dir: {}", err));
-                return;
+    /// Only list the modules importing it directly
+    #[arg(long)]
+    importers: bool,
+
+    /// The profile whose defines are used (defaults to `dev`)
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+/// Asks the user for a value, falling back to the default when nothing is entered
+fn prompt(question: &str, default: &str) -> io::Result<String> {
+    let mut input = String::new();
+    console::log_inline(&format!("{} ({}): ", question, default).magenta());
+    io::stdin().read_line(&mut input)?;
+
+    let input = input.trim();
+    if input.is_empty() {
+        Ok(default.to_string())
+    } else {
+        Ok(input.to_string())
+    }
+}
+
+/// Gets the project settings from the flags, prompting for the missing ones unless `--yes` is set
+fn read_init_inputs(args: &InitArgs) -> io::Result<(String, String, String)> {
+    let ask = |value: &Option<String>, question: &str, default: &str| match value {
+        Some(value) => Ok(value.clone()),
+        None if args.yes => Ok(default.to_string()),
+        None => prompt(question, default),
+    };
+
+    let src_dir = ask(&args.src, "Enter the source directory", "src")?;
+    let out_dir = ask(&args.out, "Enter the output directory", "out")?;
+    let entry = ask(&args.entry, "Enter the entry file", "main")?;
+
+    Ok((src_dir, out_dir, entry))
+}
+
+fn init_project(args: InitArgs) {
+    console::clear();
+    console::log(&"Initializing Project...".blue());
+
+    // Check if the config file already exists
+    if config::config_exists() {
+        console::log(&"Project file already exists".red());
+        return;
+    }
+
+    // Load the template before asking anything
+    let template = match template::Template::load(&args.template) {
+        Ok(template) => template,
+        Err(err) => {
+            console::log_error(&err.to_string());
+            return;
+        }
+    };
+
+    let (src_dir, out_dir, entry) = match read_init_inputs(&args) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            console::log_error(&format!("Error reading input: {}", err));
+            return;
+        }
+    };
+
+    // The project name defaults to the current directory's name
+    let name = args.name.clone().unwrap_or_else(|| {
+        env::current_dir()
+            .ok()
+            .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().to_string()))
+            .unwrap_or_else(|| String::from("project"))
+    });
+
+    let variables = HashMap::from([
+        ("name", name),
+        ("src_dir", src_dir.clone()),
+        ("out_dir", out_dir.clone()),
+        ("entry", entry.clone()),
+    ]);
+
+    let files = template.render(&variables);
+
+    // Plan the changes, so nothing is written if the project can't be created
+    let plan = match config::plan_project(&src_dir, &out_dir, &entry, &files) {
+        Ok(plan) => plan,
+        Err(err) => {
+            console::log_error(&format!("Problem creating project: {}", err));
+            return;
+        }
+    };
+
+    for step in plan.describe() {
+        console::log(&format!("  {}", step));
+    }
+
+    if args.dry_run {
+        console::log(&"Dry run, no changes were made".yellow());
+        return;
+    }
+
+    // Create the project files, everything is rolled back if a step fails
+    match plan.apply() {
+        Ok(_) => console::log(&"Project successfully created".green()),
+        Err(err) => console::log_error(&format!("Problem creating project: {}", err).red()),
+    };
+}
+
+/// Loads the project file and selects a profile, returning the config and any warnings about it
+fn read_config(
+    path: Option<&Path>,
+    profile: Option<&str>,
+    default: &str,
+    check_entry: bool,
+) -> Result<(config::Config, Vec<String>), String> {
+    let (mut config, mut warnings) =
+        config::load_config(path, check_entry).map_err(|err| err.to_string())?;
+    warnings.extend(config.select_profile(profile, default)?);
+
+    Ok((config, warnings))
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Some(cwd) = &cli.cwd {
+        if let Err(err) = env::set_current_dir(cwd) {
+            console::log_error(&format!("Could not change to '{}': {}", cwd.display(), err));
+            process::exit(1);
+        }
+    }
+
+    // Reads the project file, with the global flags applied to it
+    let read = |path: Option<&Path>, profile: Option<&str>, default: &str, check_entry: bool| {
+        read_config(path, profile, default, check_entry).map(|(mut config, warnings)| {
+            if cli.no_cache {
+                config.cache = Some(false);
             }
 
-            // Ask for entry file
-            let mut entry = String::new();
-            console::log_inline(&"Enter the entry file (main): ".magenta());
+            config.jobs = cli.jobs.map(|jobs| jobs as usize);
 
-            if let Err(err) = stdin.read_line(&mut entry) {
-                console::log_error(&format!("Error reading entry file: {}", err));
-                return;
+            (config, warnings)
+        })
+    };
+
+    // Same, but exits with an error message if the project file can't be used
+    let load_as = |profile: Option<&str>, default: &str, check_entry: bool| match read(
+        cli.config.as_deref(),
+        profile,
+        default,
+        check_entry,
+    ) {
+        Ok((config, warnings)) => {
+            for warning in warnings {
+                console::log_warning(&warning);
             }
 
-            // Trim the inputs
-            let src_dir = src_dir.trim();
-            let out_dir = out_dir.trim();
-            let entry = entry.trim();
+            config
+        }
+        Err(err) => {
+            console::log_error(&err);
+            process::exit(1);
+        }
+    };
+    let load = |profile: Option<&str>, default: &str| load_as(profile, default, true);
 
-            // If the inputs are empty, give them their default value
-            let src_dir = if src_dir.is_empty() { "src" } else { src_dir };
-            let out_dir = if out_dir.is_empty() { "out" } else { out_dir };
-            let entry = if entry.is_empty() { "main" } else { entry };
+    match cli.command.unwrap_or(Command::Build(BuildArgs::default())) {
+        Command::Init(args) => {
+            // The project file is always created in the current directory
+            if cli.config.is_some() {
+                console::log_error("--config can't be used with init, use --cwd <dir> instead");
+                process::exit(1);
+            }
 
-            // Create the project file
-            match config::create_config_file(src_dir, out_dir, entry) {
-                Ok(_) => console::log(&"Project successfully created".green()),
-                Err(err) => console::log_error(&format!("Problem creating project: {}", err).red()),
-            };
+            init_project(args)
         }
-        "serve" => {
+        Command::Serve(args) => {
             console::clear();
 
             // Initially check for config
-            let config = config::get_config().unwrap_or_else(|| {
-                console::log_error("Project file not found");
-                process::exit(1);
-            });
+            let mut config = load(args.profile.as_deref(), "dev");
+            args.apply(&mut config);
+
+            let path = config.path.clone();
+            let shared_config = Arc::new(RwLock::new(config.clone()));
+            let server = cli::SharedServer::default();
 
             // Run the CLI and server
-            cli::run_server(config.clone());
+            cli::run_server(shared_config.clone(), server.clone(), args.port);
 
-            // Run the bundler
-            cli::run_bundler(config.clone());
+            // Run the bundler, reloading the same project file when it changes
+            cli::run_bundler(config, shared_config, server, || {
+                read(Some(&path), args.profile.as_deref(), "dev", true).map(|(mut config, warnings)| {
+                    args.apply(&mut config);
+                    (config, warnings)
+                })
+            });
         }
-        "build" => {
-            console::clear();
+        Command::Build(args) => {
+            // The bundle or the JSON report goes to stdout then
+            let json_report = matches!(args.report, Some(cli::ReportFormat::Json));
+            if json_report {
+                console::log_to_stderr();
+            } else if !args.stdin {
+                console::clear();
+            }
 
-            let config = config::get_config().unwrap_or_else(|| {
-                console::log_error("Project file not found");
-                process::exit(1);
-            });
+            // The modules given on stdin don't have to exist on the disk
+            let mut config = load_as(args.profile.as_deref(), "prod", !args.stdin);
+            if args.no_minify {
+                config.settings.minify = false;
+            }
 
-            cli::build_project(config);
+            if args.stdin {
+                cli::build_stdin(config);
+            } else {
+                cli::build_project(config, args.report, args.diff);
+            }
+        }
+        Command::Check(args) => {
+            let config = load(args.profile.as_deref(), "dev");
+            cli::check_project(config, args.format, args.strict);
+        }
+        Command::Unused(args) => {
+            let config = load(args.profile.as_deref(), "dev");
+            cli::report_unused(config, args.fix, args.archive.as_deref());
+        }
+        Command::Graph(args) => {
+            let config = load(args.profile.as_deref(), "dev");
+            cli::print_graph(
+                config,
+                args.format,
+                args.root.as_deref(),
+                args.depth,
+                args.cycles,
+            );
+        }
+        Command::Why(args) => {
+            let config = load(args.profile.as_deref(), "dev");
+            cli::explain_module(config, &args.module, args.importers);
+        }
+        Command::Lock => {
+            let config = load(None, "prod");
+            cli::lock_dependencies(config);
+        }
+        Command::Schema => print!("{}", config::SCHEMA),
+        Command::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
         }
-        _ => console::log_error(&"Invalid action".red()),
     };
 }
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::cache::CACHE_DIR_NAME;
+use crate::config::{self, CONFIG_FILE_NAME};
+use crate::parser;
+use crate::vfs::DiskFs;
+
+/// The lockfile, next to the project file
+pub const LOCKFILE_NAME: &str = "luajoin.lock";
+
+/// The folders Wally installs packages in, at the root of a project
+pub const WALLY_FOLDERS: [&str; 3] = ["Packages", "ServerPackages", "DevPackages"];
+
+/// A local dependency. Its modules are required as `<name>/<module>`, and its entry as `<name>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub path: String, // as given in the project file, relative to the project's root
+    pub src_dir: PathBuf,
+    pub entry_file: String, // relative to its source directory, without extension
+    pub archive: Option<PathBuf>, // the archive it was extracted from, if any
+}
+
+impl Package {
+    /// Reads the project file of a dependency for its source directory and entry. A directory
+    /// without one is a source directory itself, with `init` as its entry. A `.zip` archive is
+    /// extracted first
+    pub fn load(name: &str, path: &str, root: &Path) -> Result<Self, String> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(String::from("is not a valid package name"));
+        }
+
+        let (dir, archive) = match path.ends_with(".zip") {
+            true => {
+                let archive = root.join(path);
+                (extract_archive(&archive, root)?, Some(archive))
+            }
+            false => (root.join(path), None),
+        };
+
+        if !dir.is_dir() {
+            return Err(format!("is not a directory: '{}'", dir.display()));
+        }
+
+        let project_file = dir.join(CONFIG_FILE_NAME);
+        let (src_dir, entry_file) = match project_file.is_file() {
+            true => {
+                let content = fs::read_to_string(&project_file)
+                    .map_err(|err| format!("could not be read: {}", err))?;
+                let (dependency, _) = config::parse_config(&content, &project_file)
+                    .map_err(|err| format!("has an invalid project file: {}", err))?;
+
+                (dir.join(dependency.src_dir), dependency.entry_file)
+            }
+            false => (dir, String::from("init")),
+        };
+
+        if !parser::module_exists(&DiskFs, &src_dir.to_string_lossy(), &entry_file) {
+            return Err(format!(
+                "has no entry module '{}' in '{}'",
+                entry_file,
+                src_dir.display()
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            path: path.to_string(),
+            src_dir,
+            entry_file,
+            archive,
+        })
+    }
+
+    /// A hash of the names and contents of the files in the source directory, and of the entry.
+    /// Hidden files, like editor swap files, are left out. An archive's hash is the one of its bytes
+    pub fn hash(&self) -> io::Result<String> {
+        if let Some(archive) = &self.archive {
+            return Ok(format!("sha1-{}", to_hex(&Sha1::digest(fs::read(archive)?))));
+        }
+
+        let mut files = Vec::new();
+        list_files(&self.src_dir, "", &mut files)?;
+        files.sort();
+
+        let mut hasher = Sha1::new();
+        hasher.update(self.entry_file.as_bytes());
+
+        // Each part is prefixed by its length, so moving bytes from a part to the next shows
+        for file in files {
+            let content = fs::read(self.src_dir.join(&file))?;
+
+            for part in [file.as_bytes(), &content] {
+                hasher.update((part.len() as u64).to_le_bytes());
+                hasher.update(part);
+            }
+        }
+
+        Ok(format!("sha1-{}", to_hex(&hasher.finalize())))
+    }
+}
+
+/// Extracts an archive in the cache, in a directory named after its hash so it's only extracted
+/// once. An archive holding a single directory, as they often do, stands for that directory
+fn extract_archive(archive: &Path, root: &Path) -> Result<PathBuf, String> {
+    let content = fs::read(archive).map_err(|err| format!("could not be read: {}", err))?;
+    let dir = root
+        .join(CACHE_DIR_NAME)
+        .join("packages")
+        .join(to_hex(&Sha1::digest(&content)));
+
+    if !dir.is_dir() {
+        // Extracted next to it first, so an interrupted extraction is never used
+        let partial = dir.with_extension("partial");
+        let _ = fs::remove_dir_all(&partial);
+
+        zip::ZipArchive::new(io::Cursor::new(content))
+            .and_then(|mut zip| zip.extract(&partial))
+            .map_err(|err| format!("could not be extracted: {}", err))?;
+        fs::rename(&partial, &dir).map_err(|err| format!("could not be extracted: {}", err))?;
+    }
+
+    let entries: Vec<PathBuf> = fs::read_dir(&dir)
+        .map_err(|err| format!("could not be extracted: {}", err))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+
+    match entries.as_slice() {
+        [only] if only.is_dir() => Ok(only.clone()),
+        _ => Ok(dir),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The folders Wally installed packages in, in the source directory or else at the project's
+/// root. They are packages named after the folder, required like `Packages/Promise`
+pub fn wally_packages(root: &Path, src_dir: &Path) -> Vec<Package> {
+    WALLY_FOLDERS
+        .iter()
+        .filter_map(|folder| {
+            let dir = [src_dir, root]
+                .iter()
+                .map(|dir| dir.join(folder))
+                .find(|dir| dir.is_dir())?;
+
+            Some(Package {
+                name: folder.to_string(),
+                path: folder.to_string(),
+                src_dir: dir,
+                entry_file: String::from("init"),
+                archive: None,
+            })
+        })
+        .collect()
+}
+
+/// Lists the files of a directory recursively, relative to it and using `/`
+fn list_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let relative = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &format!("{}/", relative), files)?;
+        } else {
+            files.push(relative);
+        }
+    }
+
+    Ok(())
+}
+
+/// What the lockfile records of a dependency
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LockedPackage {
+    pub path: String,
+    pub hash: String,
+}
+
+/// The dependencies as they were when they were locked, so a build can tell if they changed since
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub struct Lockfile {
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+impl Lockfile {
+    /// Locks the dependencies as they are now
+    pub fn new(packages: &[Package]) -> io::Result<Self> {
+        let mut locked = BTreeMap::new();
+        for package in packages {
+            let hash = package.hash()?;
+            locked.insert(
+                package.name.clone(),
+                LockedPackage {
+                    path: package.path.clone(),
+                    hash,
+                },
+            );
+        }
+
+        Ok(Self { packages: locked })
+    }
+
+    /// Reads a lockfile, `None` if there's none
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("Could not read '{}': {}", path.display(), err)),
+        };
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|err| format!("'{}' is invalid: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    /// Describes how the dependencies changed since they were locked
+    pub fn changes_since(&self, locked: &Lockfile) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for (name, package) in &self.packages {
+            match locked.packages.get(name) {
+                None => changes.push(format!("Dependency '{}' is not locked", name)),
+                Some(old) if old.path != package.path => changes.push(format!(
+                    "Dependency '{}' moved from '{}' to '{}'",
+                    name, old.path, package.path
+                )),
+                Some(old) if old.hash != package.hash => {
+                    changes.push(format!("Dependency '{}' changed since it was locked", name))
+                }
+                Some(_) => (),
+            }
+        }
+
+        for name in locked.packages.keys() {
+            if !self.packages.contains_key(name) {
+                changes.push(format!("Dependency '{}' was removed", name));
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BundleOptions, Bundler, MemoryFs};
+
+    #[test]
+    fn bundles_dependencies() {
+        let memory_fs: MemoryFs = [
+            ("src/main.lua", "return require(\"strings\").upper(require(\"./name\"))"),
+            ("src/name.lua", "return \"a\""),
+            ("libs/strings/src/init.lua", "return require(\"./upper\")"),
+            ("libs/strings/src/upper.lua", "return { upper = string.upper }"),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut options = BundleOptions::new("src", "main");
+        options.packages = vec![Package {
+            name: String::from("strings"),
+            path: String::from("libs/strings"),
+            src_dir: PathBuf::from("libs/strings/src"),
+            entry_file: String::from("init"),
+            archive: None,
+        }];
+
+        let mut bundler = Bundler::new(options).with_fs(memory_fs);
+        let output = bundler.bundle().unwrap();
+
+        assert_eq!(output.modules, vec!["main", "strings", "name", "strings/upper"]);
+        assert!(output.code.contains("__LUAJOIN_DIRECTORIES[\"strings\"]=true"));
+        assert_eq!(
+            bundler.module_file("strings/upper").as_deref(),
+            Some("libs/strings/src/upper.lua")
+        );
+    }
+
+    #[test]
+    fn extracts_archives() {
+        let root = std::env::temp_dir().join(format!("luajoin-package-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        let write_archive = |upper: &str| {
+            let file = fs::File::create(root.join("strings.zip")).unwrap();
+            let mut zip = zip::ZipWriter::new(file);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+
+            let files = [
+                ("strings/init.lua", "return require(\"./upper\")"),
+                ("strings/upper.lua", upper),
+            ];
+            for (name, content) in files {
+                zip.start_file(name, options).unwrap();
+                io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+        };
+
+        write_archive("return string.upper");
+        let package = Package::load("strings", "strings.zip", &root).unwrap();
+        let hash = package.hash().unwrap();
+
+        assert!(package.src_dir.starts_with(root.join(CACHE_DIR_NAME)));
+        assert!(package.src_dir.join("upper.lua").is_file());
+        assert_eq!(Package::load("strings", "strings.zip", &root).unwrap(), package);
+
+        write_archive("return string.lower");
+        let changed = Package::load("strings", "strings.zip", &root).unwrap();
+
+        assert_ne!(changed.src_dir, package.src_dir);
+        assert_ne!(changed.hash().unwrap(), hash);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reports_changes_since_locked() {
+        let locked = |packages: &[(&str, &str, &str)]| Lockfile {
+            packages: packages
+                .iter()
+                .map(|(name, path, hash)| {
+                    let package = LockedPackage {
+                        path: path.to_string(),
+                        hash: hash.to_string(),
+                    };
+
+                    (name.to_string(), package)
+                })
+                .collect(),
+        };
+
+        let old = locked(&[("a", "libs/a", "1"), ("b", "libs/b", "2"), ("c", "libs/c", "3")]);
+        let new = locked(&[("a", "libs/a", "1"), ("b", "libs/b", "4"), ("d", "libs/d", "5")]);
+
+        assert!(old.changes_since(&old).is_empty());
+        assert_eq!(
+            new.changes_since(&old),
+            vec![
+                "Dependency 'b' changed since it was locked",
+                "Dependency 'd' is not locked",
+                "Dependency 'c' was removed",
+            ]
+        );
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::{HashMap, HashSet};
-use std::error::Error;
-use std::path::Path;
-use std::{fmt, fs};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 use full_moon::ast::punctuated::{Pair, Punctuated};
 use full_moon::ast::span::ContainedSpan;
@@ -11,7 +12,13 @@ use full_moon::node::Node;
 use full_moon::tokenizer::{StringLiteralQuoteType, Symbol, Token, TokenReference, TokenType};
 use full_moon::visitors::VisitorMut;
 
+use crate::bundler::{BundleError, BundleOutput, SourceMap};
+use crate::cache::{Cache, CachedModule};
+use crate::diagnostic::Diagnostic;
+use crate::package::{Package, WALLY_FOLDERS};
 use crate::path::parse_path;
+use crate::preprocess::{keep_all_branches, preprocess};
+use crate::vfs::{DiskFs, FileSystem};
 
 enum ModuleType {
     Directory,
@@ -20,49 +27,92 @@ enum ModuleType {
 }
 
 const HEADER: &str = include_str!("lua/header.lua");
-#[derive(Debug, Clone)]
-struct RequireError {
-    value: String,
-}
-
-impl RequireError {
-    pub fn new(value: String) -> Self {
-        Self { value }
-    }
-}
 
-impl fmt::Display for RequireError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "RequireError: {}", self.value)
-    }
-}
-
-impl Error for RequireError {
-    fn description(&self) -> &str {
-        &self.value
-    }
-}
+/// The project file Rojo reads in a directory, which Wally packages have
+const ROJO_PROJECT_FILE: &str = "default.project.json";
 
-fn get_module_path(src_dir: &str, file_name: &str) -> Result<(String, ModuleType), String> {
+fn get_module_path(
+    fs: &dyn FileSystem,
+    src_dir: &str,
+    file_name: &str,
+) -> Result<(String, ModuleType), String> {
     let file_path = format!("{}/{}.lua", src_dir, file_name);
 
-    if Path::new(&file_path).exists() {
+    if fs.is_file(Path::new(&file_path)) {
         return Ok((file_path, ModuleType::Lua));
     }
 
     let dir_path = format!("{}/{}/init.lua", src_dir, file_name);
-    if Path::new(&dir_path).exists() {
+    if fs.is_file(Path::new(&dir_path)) {
         return Ok((dir_path, ModuleType::Directory));
     }
 
     let json_path = format!("{}/{}.json", src_dir, file_name);
-    if Path::new(&json_path).exists() {
+    if fs.is_file(Path::new(&json_path)) {
         return Ok((json_path, ModuleType::Json));
     }
 
+    // Packages, like the ones Wally installs, may have their sources elsewhere in their directory
+    let rojo_path = rojo_path(fs, src_dir, file_name);
+    if rojo_path != format!("{}/{}", src_dir, file_name) {
+        if fs.is_file(Path::new(&rojo_path)) {
+            return match rojo_path.ends_with("/init.lua") {
+                true => Ok((rojo_path, ModuleType::Directory)),
+                false => Ok((rojo_path, ModuleType::Lua)),
+            };
+        }
+
+        if let Ok(module) = get_module_path(fs, &rojo_path, "init") {
+            return Ok((module.0, ModuleType::Directory));
+        }
+
+        if let Some((dir, name)) = rojo_path.rsplit_once('/') {
+            if let Ok(module) = get_module_path(fs, dir, name) {
+                return Ok(module);
+            }
+        }
+    }
+
     Err(format!("Module '{}' not found", file_name))
 }
 
+/// Where a module's path leads as Rojo sees it: a directory with a `default.project.json` stands
+/// for the `$path` of its tree
+fn rojo_path(fs: &dyn FileSystem, src_dir: &str, file_name: &str) -> String {
+    let mut path = src_dir.to_string();
+
+    for component in file_name.split('/') {
+        path = format!("{}/{}", path, component);
+
+        let project_file = format!("{}/{}", path, ROJO_PROJECT_FILE);
+        if !fs.is_file(Path::new(&project_file)) {
+            continue;
+        }
+
+        let tree_path = fs
+            .read_to_string(Path::new(&project_file))
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .and_then(|project| project["tree"]["$path"].as_str().map(str::to_string));
+
+        if let Some(tree_path) = tree_path {
+            path = format!("{}/{}", path, tree_path.trim_end_matches('/'));
+        }
+    }
+
+    path
+}
+
+/// Whether a module (relative to the source directory, without extension) exists
+pub fn module_exists(fs: &dyn FileSystem, src_dir: &str, module: &str) -> bool {
+    get_module_path(fs, src_dir, module).is_ok()
+}
+
+/// The file of a module, relative to the current directory
+pub fn module_file(fs: &dyn FileSystem, src_dir: &str, module: &str) -> Option<String> {
+    get_module_path(fs, src_dir, module).ok().map(|(path, _)| path)
+}
+
 pub fn json_to_lua(json: &serde_json::Value) -> ast::Value {
     match json {
         serde_json::Value::Object(obj) => {
@@ -150,230 +200,857 @@ pub fn json_to_lua(json: &serde_json::Value) -> ast::Value {
     }
 }
 
-pub struct RequireVisitor<'a> {
+pub struct RequireVisitor {
     // Parsing information
-    src_dir: &'a str,
-    entry_file: &'a str,
+    src_dir: String,
+    entry_file: String,
+    development: bool, // whether the development modules are bundled
+    defines: BTreeMap<String, bool>, // the names `--#if` conditions are evaluated against
+    all_branches: bool, // whether every `--#if` branch is kept instead
 
-    // Keeping track of current state
-    cur_file: String,         // as a relative path, from src_dir, without extension
-    cur_imports: Vec<String>, // as a relative path, from cur_file, so like ./../hello/.., without extension
-    cur_errors: Vec<String>,
+    jobs: usize, // how many modules are read at once
+    fs: Arc<dyn FileSystem>, // where the modules are read from
+    packages: HashMap<String, Package>, // the local dependencies, by name
 
     // Final state
     imports_memo: HashMap<String, Vec<String>>, // as a relative path, from the src_dir, without extension
+    import_lines_memo: HashMap<String, HashMap<String, usize>>, // the line of the first require of each import
+    dynamic_requires_memo: HashMap<String, Vec<usize>>,
     transformed_memo: HashMap<String, String>, // as a relative path, from the src_dir, without extension. This is the transformed lua code
     all_json: HashMap<String, String>, // same as all_imports, but for filename to lua table of json
+    importers_memo: HashMap<String, BTreeSet<String>>, // the reverse of imports_memo
+
+    // Incremental state, so a rebuild only redoes what an edit touched
+    traversal: Option<Traversal>,
+    changed: HashMap<String, Vec<String>>, // the modules changed since, and the imports they had
+    chunks_memo: HashMap<String, (String, usize)>, // the part of the bundle of each module, and its line breaks
+
+    cache: Option<Cache>, // keeps the visited modules between runs
+    cache_dir: Option<PathBuf>,
 }
 
-impl<'a> RequireVisitor<'a> {
-    pub fn new(src_dir: &'a str, entry_file: &'a str) -> Self {
+impl RequireVisitor {
+    pub fn new(
+        src_dir: &str,
+        entry_file: &str,
+        development: bool,
+        mut defines: BTreeMap<String, bool>,
+    ) -> Self {
+        // `dev` always matches the kind of build
+        defines.insert(String::from("dev"), development);
+
         Self {
-            src_dir,
-            entry_file,
+            src_dir: src_dir.to_string(),
+            entry_file: entry_file.to_string(),
+            development,
+            defines,
+            all_branches: false,
 
-            cur_file: src_dir.to_string(),
-            cur_imports: Vec::new(),
-            cur_errors: Vec::new(),
+            jobs: 1,
+            fs: Arc::new(DiskFs),
+            packages: HashMap::new(),
 
             imports_memo: HashMap::new(),
+            import_lines_memo: HashMap::new(),
+            dynamic_requires_memo: HashMap::new(),
             transformed_memo: HashMap::new(),
             all_json: HashMap::new(),
+            importers_memo: HashMap::new(),
+
+            traversal: None,
+            changed: HashMap::new(),
+            chunks_memo: HashMap::new(),
+
+            cache: None,
+            cache_dir: None,
+        }
+    }
+
+    /// Keeps the visited modules in the given directory, to skip them in the next runs
+    pub fn with_cache(mut self, dir: &Path) -> Self {
+        self.cache = Some(Cache::new(dir, &self.defines, &self.package_names()));
+        self.cache_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Keeps every `--#if` branch, to find the modules some defines require. The cache is left
+    /// alone, as it knows the modules as the defines see them
+    pub fn with_all_branches(mut self) -> Self {
+        self.all_branches = true;
+        self
+    }
+
+    /// Reads up to this many modules at once. The bundle is the same whatever the number
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Reads the modules from the given file system instead of the disk
+    pub fn with_fs(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// Resolves the modules named after a package into its source directory
+    pub fn with_packages(mut self, packages: &[Package]) -> Self {
+        self.packages = packages
+            .iter()
+            .map(|package| (package.name.clone(), package.clone()))
+            .collect();
+
+        // The cached modules depend on the packages
+        if let Some(dir) = self.cache_dir.take() {
+            return self.with_cache(&dir);
         }
+
+        self
+    }
+
+    fn package_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.packages.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The package a module is part of
+    fn package_of(&self, module: &str) -> Option<&Package> {
+        let name = module.split('/').next()?;
+        self.packages.get(name)
+    }
+
+    /// The file system the modules are read from
+    pub fn fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    /// The file of a module, and its type. The entry of a package is required by the package's name,
+    /// and requires relative to its source directory as an `init` file would
+    fn resolve(&self, module: &str) -> Result<(String, ModuleType), String> {
+        let (name, rest) = match module.split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (module, None),
+        };
+
+        let package = match self.packages.get(name) {
+            Some(package) => package,
+            None => return get_module_path(self.fs(), &self.src_dir, module),
+        };
+
+        let src_dir = package.src_dir.to_string_lossy();
+        let resolved = match rest {
+            Some(rest) => get_module_path(self.fs(), &src_dir, rest),
+            None => get_module_path(self.fs(), &src_dir, &package.entry_file).map(
+                |(path, module_type)| match module_type {
+                    ModuleType::Lua => (path, ModuleType::Directory),
+                    module_type => (path, module_type),
+                },
+            ),
+        };
+
+        resolved.map_err(|_| format!("Module '{}' not found in package '{}'", module, name))
+    }
+
+    /// The file of a module, relative to the current directory
+    pub fn module_file(&self, module: &str) -> Option<String> {
+        self.resolve(module).ok().map(|(path, _)| path)
     }
 
     /// Removes a file from the cached, and rebuilds the project
     pub fn mark_file_change(&mut self, file: &str) {
-        self.imports_memo.remove(file);
-        self.transformed_memo.remove(file);
-        self.all_json.remove(file);
+        // The first change since the last traversal is the one to compare the imports with
+        if let Some(imports) = self.forget(file) {
+            self.changed.entry(file.to_string()).or_insert(imports);
+        }
     }
 
-    /// Builds the project
-    pub fn generate_bundle(
-        &mut self,
-        development: bool,
-    ) -> Result<(String, Vec<usize>, Vec<String>), Box<dyn Error>> {
-        // Traverse the file tree to get the imports
-        let mut imports = self.traverse()?;
-        let mut bundle = String::from(HEADER);
-        let mut source_map: Vec<usize> = Vec::new();
+    /// Removes a module from the memos and the reverse graph, returning its imports if it was
+    /// visited
+    fn forget(&mut self, module: &str) -> Option<Vec<String>> {
+        self.chunks_memo.remove(module);
+        self.import_lines_memo.remove(module);
+        self.dynamic_requires_memo.remove(module);
+        self.transformed_memo.remove(module);
 
-        source_map.push(HEADER.split("\n").count());
+        let imports = match self.imports_memo.remove(module) {
+            Some(imports) => imports,
+            None => return self.all_json.remove(module).map(|_| Vec::new()),
+        };
 
-        // If we are in development, add the development code
-        let mut dev_file_exists = false;
-        if development {
-            // Find if the development file exists
-            let dev_file_path = format!("{}/{}", self.src_dir, ".dev.lua");
-            if Path::new(&dev_file_path).exists() {
-                dev_file_exists = true;
+        for import in &imports {
+            if let Some(importers) = self.importers_memo.get_mut(import) {
+                importers.remove(module);
+                if importers.is_empty() {
+                    self.importers_memo.remove(import);
+                }
             }
         }
 
-        if dev_file_exists {
-            imports.push(String::from(".dev"));
+        Some(imports)
+    }
+
+    /// Removes every module in a directory from the cached, returning how many were visited
+    pub fn mark_dir_change(&mut self, dir: &str) -> usize {
+        let prefix = format!("{}/", dir);
+        let modules: Vec<String> = self
+            .imports_memo
+            .keys()
+            .chain(self.all_json.keys())
+            .filter(|module| dir.is_empty() || *module == dir || module.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for module in &modules {
+            self.mark_file_change(module);
         }
 
-        // Add every import
-        for import in &imports {
-            let (module_path, module_type) = get_module_path(self.src_dir, &import)?;
+        modules.len()
+    }
 
-            let module_content = match module_type {
-                ModuleType::Lua | ModuleType::Directory => {
-                    match self.transformed_memo.get(import) {
-                        Some(lua) => lua.to_string(),
-                        None => fs::read_to_string(module_path)?,
-                    }
-                }
-                ModuleType::Json => {
-                    let json_lua = self.all_json.get(import).unwrap();
-                    json_lua.to_string()
-                }
-            };
+    /// Builds the project. In development, the given development modules are ran before the entry
+    /// file. Otherwise, they are left out, and no other module may import them
+    pub fn generate_bundle(&mut self, dev_files: &[String]) -> Result<BundleOutput, BundleError> {
+        let dev_files = self.existing_modules(dev_files);
 
-            if let ModuleType::Directory = module_type {
-                let dir_header = format!("\n__LUAJOIN_DIRECTORIES[\"{}\"]=true", import);
-                bundle.push_str(&dir_header)
-            }
+        // Traverse the file tree to get the imports
+        let roots = self.roots(&dev_files);
+        let imports = self.traverse(&roots)?;
 
-            let import_header = format!("\n__LUAJOIN_FILES[\"{}\"]=function(_require)\n", import);
-            let import_footer = "\nend";
+        if !self.development {
+            self.check_dev_imports(&imports, &dev_files)?;
+        }
+
+        let dev_files = if self.development { dev_files } else { Vec::new() };
+        let mut bundle = String::from(HEADER);
+        let mut source_map: Vec<usize> = Vec::new();
+
+        source_map.push(HEADER.split("\n").count());
+
+        // Add every import, they were all visited by the traversal. Only the modules that changed
+        // since the last bundle are formatted again
+        let mut line_breaks = HEADER.matches('\n').count();
+        for import in &imports {
+            if !self.chunks_memo.contains_key(import) {
+                let chunk = self.bundle_chunk(import)?;
+                self.chunks_memo.insert(import.clone(), chunk);
+            }
 
-            bundle.push_str(&(import_header + &module_content + import_footer));
+            let (chunk, chunk_line_breaks) = &self.chunks_memo[import];
+            bundle.push_str(chunk);
 
             // Add the data to the source map
-            source_map.push(bundle.split('\n').count());
+            line_breaks += chunk_line_breaks;
+            source_map.push(line_breaks + 1);
         }
 
         // Add the dev footer
-        if dev_file_exists {
-            bundle.push_str("\n__LUAJOIN_FILES[\".dev\"](__LUAJOIN_require)");
+        for dev_file in &dev_files {
+            bundle.push_str(&format!(
+                "\n__LUAJOIN_FILES[\"{}\"](__LUAJOIN_require)",
+                dev_file
+            ));
         }
 
-        // Add the footer, which will require the entry file
+        // Add the footer, which will require the entry file and return its value
         bundle.push_str(&format!(
-            "\n__LUAJOIN_FILES[\"{}\"](__LUAJOIN_require)\n",
+            "\nreturn __LUAJOIN_FILES[\"{}\"](__LUAJOIN_require)\n",
             self.entry_file
         ));
 
-        // prepend something
-        imports.insert(0, String::from("[BUNDLER]"));
+        // The source map starts with the bundler's own code
+        let mut files = imports.clone();
+        files.insert(0, String::from("[BUNDLER]"));
+
+        Ok(BundleOutput {
+            code: bundle,
+            source_map: SourceMap {
+                files,
+                sources: source_map,
+            },
+            modules: imports,
+            diagnostics: Vec::new(),
+        })
+    }
+
+    /// The part of the bundle defining a visited module, and how many line breaks it has
+    fn bundle_chunk(&self, module: &str) -> Result<(String, usize), Diagnostic> {
+        let (_, module_type) = self.resolve(module)
+            .map_err(|err| Diagnostic::error("unresolved", err).with_module(module))?;
+        let module_content = self.module_code(module).unwrap_or_default();
+
+        let mut chunk = String::new();
+        if let ModuleType::Directory = module_type {
+            chunk.push_str(&format!("\n__LUAJOIN_DIRECTORIES[\"{}\"]=true", module));
+        }
+
+        chunk.push_str(&format!("\n__LUAJOIN_FILES[\"{}\"]=function(_require)\n", module));
+        chunk.push_str(module_content);
+        chunk.push_str("\nend");
 
-        Ok((bundle, source_map, imports))
+        let line_breaks = chunk.matches('\n').count();
+        Ok((chunk, line_breaks))
     }
 
-    /// Traverse the file tree, to return a list of all the files that are imported
-    pub fn traverse(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
-        // First, clear the temporary storages
-        {
-            self.cur_imports.clear();
-            self.cur_errors.clear();
+    fn existing_modules(&self, modules: &[String]) -> Vec<String> {
+        modules
+            .iter()
+            .filter(|module| self.resolve(module).is_ok())
+            .cloned()
+            .collect()
+    }
+
+    /// The modules the bundle is built from: the entry file, and the development modules that
+    /// exist when they are bundled
+    pub fn roots(&self, dev_files: &[String]) -> Vec<String> {
+        let mut roots = vec![self.entry_file.clone()];
+        if self.development {
+            roots.extend(self.existing_modules(dev_files));
         }
 
-        let mut i = 0;
-        let mut all_file_imports = vec![self.entry_file.to_string()];
-        let mut all_file_imports_set: HashSet<String> = HashSet::new();
+        roots
+    }
+
+    /// The direct imports of every module visited so far
+    pub fn imports(&self) -> &HashMap<String, Vec<String>> {
+        &self.imports_memo
+    }
+
+    /// The visited modules importing a module directly
+    pub fn importers(&self, module: &str) -> Vec<&String> {
+        self.importers_memo
+            .get(module)
+            .map_or_else(Vec::new, |importers| importers.iter().collect())
+    }
 
-        while i < all_file_imports.len() {
-            let import = all_file_imports.get(i).unwrap();
+    /// The code of a visited module, as it is put in the bundle
+    pub fn module_code(&self, module: &str) -> Option<&str> {
+        self.transformed_memo
+            .get(module)
+            .or_else(|| self.all_json.get(module))
+            .map(String::as_str)
+    }
 
-            // Get the import's file
-            let (module_path, module_type) = get_module_path(self.src_dir, &import)?;
+    /// The line of the first require of an import in a module
+    pub fn import_line(&self, module: &str, import: &str) -> Option<usize> {
+        self.import_lines_memo.get(module)?.get(import).copied()
+    }
 
-            // if it's json do something else
-            if let ModuleType::Json = module_type {
-                // If it's already parsed, then we don't need to visit it again
-                if self.all_json.contains_key(import) {
-                    i += 1;
-                    continue;
+    /// Makes sure no production module imports a development module, a module inside of one, or a
+    /// module only reachable through them. The production modules are the ones the entry file
+    /// reaches without going through a development module
+    fn check_dev_imports(&self, imports: &[String], dev_files: &[String]) -> Result<(), BundleError> {
+        let is_dev_file = |module: &str| {
+            dev_files.iter().any(|dev_file| {
+                module == dev_file || module.starts_with(&format!("{}/", dev_file))
+            })
+        };
+
+        let mut production: HashSet<&str> = HashSet::from([self.entry_file.as_str()]);
+        let mut queue = VecDeque::from([self.entry_file.as_str()]);
+        while let Some(module) = queue.pop_front() {
+            for import in self.imports_memo.get(module).into_iter().flatten() {
+                if !is_dev_file(import) && production.insert(import.as_str()) {
+                    queue.push_back(import.as_str());
                 }
+            }
+        }
 
-                let module_content = fs::read_to_string(&module_path)?;
+        for importer in imports.iter().filter(|module| production.contains(module.as_str())) {
+            for import in self.imports_memo.get(importer).into_iter().flatten() {
+                if is_dev_file(import) || !production.contains(import.as_str()) {
+                    return Err(BundleError::DevImport {
+                        importer: importer.clone(),
+                        module: import.clone(),
+                    });
+                }
+            }
+        }
 
-                // Parse the json
-                let json = serde_json::from_str(&module_content)?;
-                let lua = json_to_lua(&json).to_string();
+        Ok(())
+    }
+
+    /// Traverse the file tree from the given modules, to return a list of all the files that are
+    /// imported. When the modules changed since the last traversal kept the same imports, its list
+    /// is reused and only they are visited again
+    pub fn traverse(&mut self, roots: &[String]) -> Result<Vec<String>, Diagnostic> {
+        if let Some(modules) = self.reuse_traversal(roots) {
+            return Ok(modules);
+        }
 
-                self.all_json
-                    .insert(import.clone(), "return ".to_owned() + &lua);
+        self.traversal = None;
+        self.changed.clear();
 
-                i += 1;
+        let modules = self.walk(roots)?;
+
+        // Forget the modules that were left behind, they would be visited again if they come back
+        let reachable: HashSet<String> = modules.iter().cloned().collect();
+        let unreachable: Vec<String> = self
+            .imports_memo
+            .keys()
+            .chain(self.all_json.keys())
+            .filter(|module| !reachable.contains(*module))
+            .cloned()
+            .collect();
+
+        for module in unreachable {
+            self.forget(&module);
+        }
+
+        self.traversal = Some(Traversal {
+            roots: roots.to_vec(),
+            modules: modules.clone(),
+            reachable,
+        });
+
+        Ok(modules)
+    }
+
+    /// The modules of the last traversal, if it had the same roots and none of the reachable
+    /// modules changed since gained or lost an import
+    fn reuse_traversal(&mut self, roots: &[String]) -> Option<Vec<String>> {
+        let traversal = self.traversal.take()?;
+        if traversal.roots != roots {
+            return None;
+        }
+
+        for (module, imports) in std::mem::take(&mut self.changed) {
+            // Changes to the modules outside of the bundle don't matter
+            if !traversal.reachable.contains(&module) {
                 continue;
             }
 
-            // If it's already visited, then we don't need to visit it again
-            if self.imports_memo.contains_key(import) {
-                let import_memo = self.imports_memo.get(import).unwrap();
-                for import in import_memo {
-                    // Only insert the one's that are not there yet
-                    if all_file_imports_set.contains(import) {
-                        continue;
-                    }
+            match self.visit_module(&module) {
+                Ok(new_imports) if new_imports == imports => (),
+                _ => return None,
+            }
+        }
 
-                    all_file_imports_set.insert(import.clone());
-                    all_file_imports.push(import.clone());
+        let modules = traversal.modules.clone();
+        self.traversal = Some(traversal);
+
+        Some(modules)
+    }
+
+    /// Walks the imports breadth first from each root, visiting the modules not visited yet
+    fn walk(&mut self, roots: &[String]) -> Result<Vec<String>, Diagnostic> {
+        // Read the modules in parallel first, the walk below then only uses the memos
+        if self.jobs > 1 {
+            self.load_reachable(roots);
+        }
+
+        let mut i = 0;
+        let mut all_file_imports: Vec<String> = Vec::new();
+        let mut all_file_imports_set: HashSet<String> = HashSet::new();
+        let mut first_importers: HashMap<String, String> = HashMap::new();
+
+        for root in roots {
+            // Each root is visited after everything imported by the previous ones
+            if all_file_imports_set.insert(root.clone()) {
+                all_file_imports.push(root.clone());
+            }
+
+            while i < all_file_imports.len() {
+                let import = all_file_imports[i].clone();
+                let imports = self
+                    .visit_module(&import)
+                    .map_err(|diagnostic| self.locate_missing(diagnostic, first_importers.get(&import)))?;
+
+                // Only insert the ones that are not there yet
+                for dep in imports {
+                    if all_file_imports_set.insert(dep.clone()) {
+                        first_importers.insert(dep.clone(), import.clone());
+                        all_file_imports.push(dep);
+                    }
                 }
 
                 i += 1;
-                continue;
             }
+        }
+
+        Ok(all_file_imports)
+    }
+
+    /// Same as `traverse`, but keeps going after a problem to report all of them. The modules that
+    /// could not be visited are left out of the list
+    pub fn check(&mut self, roots: &[String]) -> (Vec<String>, Vec<Diagnostic>) {
+        // Read the modules in parallel first, the walk below then only uses the memos
+        if self.jobs > 1 {
+            self.load_reachable(roots);
+        }
+
+        let mut i = 0;
+        let mut all_file_imports: Vec<String> = Vec::new();
+        let mut all_file_imports_set: HashSet<String> = HashSet::new();
+        let mut first_importers: HashMap<String, String> = HashMap::new();
+        let mut diagnostics = Vec::new();
+
+        for root in roots {
+            if all_file_imports_set.insert(root.clone()) {
+                all_file_imports.push(root.clone());
+            }
+
+            while i < all_file_imports.len() {
+                let import = all_file_imports[i].clone();
+
+                match self.visit_module(&import) {
+                    Ok(imports) => {
+                        for dep in imports {
+                            if all_file_imports_set.insert(dep.clone()) {
+                                first_importers.insert(dep.clone(), import.clone());
+                                all_file_imports.push(dep);
+                            }
+                        }
+
+                        diagnostics.extend(self.dynamic_require_warnings(&import));
+
+                        i += 1;
+                    }
+                    Err(diagnostic) => {
+                        diagnostics.push(self.locate_missing(diagnostic, first_importers.get(&import)));
+                        all_file_imports.remove(i);
+                    }
+                }
+            }
+        }
+
+        (all_file_imports, diagnostics)
+    }
 
-            // If it's not visited, then visit it
-            let module_content = fs::read_to_string(&module_path)?;
-            let module_ast = full_moon::parse(&module_content)?;
+    /// Points a missing module at the require asking for it, as the module has no file to point at
+    fn locate_missing(&self, diagnostic: Diagnostic, importer: Option<&String>) -> Diagnostic {
+        let (module, importer) = match (&diagnostic.module, importer) {
+            (Some(module), Some(importer)) if diagnostic.code == "unresolved" => (module, importer),
+            _ => return diagnostic,
+        };
 
-            self.cur_file = import.clone();
-            self.cur_imports.clear();
-            self.cur_errors.clear();
+        Diagnostic::error(
+            "unresolved",
+            format!("'{}' requires '{}', which does not exist", importer, module),
+        )
+        .with_module(importer)
+        .with_file(&self.module_file(importer).unwrap_or_default())
+        .with_line(self.import_line(importer, module))
+    }
+
+    /// Warns about the requires whose path is computed at runtime in a visited module
+    pub fn dynamic_require_warnings(&self, module: &str) -> Vec<Diagnostic> {
+        let lines = match self.dynamic_requires_memo.get(module) {
+            Some(lines) => lines,
+            None => return Vec::new(),
+        };
+
+        lines
+            .iter()
+            .map(|line| {
+                Diagnostic::warning(
+                    "dynamic-require",
+                    String::from("Require with a computed path, it is left as is"),
+                )
+                .with_module(module)
+                .with_file(&self.module_file(module).unwrap_or_default())
+                .with_line(Some(*line))
+            })
+            .collect()
+    }
 
-            let new_ast = self.visit_ast(module_ast);
+    /// Reads every module reachable from the roots that isn't visited yet, over the worker threads.
+    /// Modules that can't be read are left for `traverse` or `check` to report, in order
+    fn load_reachable(&mut self, roots: &[String]) {
+        let mut failed: HashSet<String> = HashSet::new();
 
-            // If there's errors, then we can't continue
-            if !self.cur_errors.is_empty() {
-                let first_error = self.cur_errors.get(0).unwrap().clone().to_string();
+        loop {
+            let pending = self.pending_modules(roots, &failed);
+            if pending.is_empty() {
+                return;
+            }
 
-                return Err(Box::new(RequireError::new(format!(
-                    "'{}': {}",
-                    import, first_error
-                ))));
+            let next = AtomicUsize::new(0);
+            let loaded: Vec<(usize, Result<LoadedModule, Diagnostic>)> = thread::scope(|scope| {
+                let workers: Vec<_> = (0..self.jobs.min(pending.len()))
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut loaded = Vec::new();
+
+                            loop {
+                                let i = next.fetch_add(1, Ordering::Relaxed);
+                                match pending.get(i) {
+                                    Some(module) => loaded.push((i, self.load_module(module))),
+                                    None => return loaded,
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().unwrap())
+                    .collect()
+            });
+
+            for (i, result) in loaded {
+                match result {
+                    Ok(module) => self.remember(&pending[i], module),
+                    Err(_) => {
+                        failed.insert(pending[i].clone());
+                    }
+                }
             }
+        }
+    }
+
+    /// The modules reachable from the roots through the visited ones, that are not visited yet
+    fn pending_modules(&self, roots: &[String], failed: &HashSet<String>) -> Vec<String> {
+        let mut seen: HashSet<&String> = roots.iter().collect();
+        let mut queue: VecDeque<&String> = roots.iter().collect();
+        let mut pending = Vec::new();
 
-            // Parse all the relative imports
-            let mut rel_imports: Vec<String> = Vec::new();
-            let mut rel_imports_set: HashSet<String> = HashSet::new();
+        while let Some(module) = queue.pop_front() {
+            if self.all_json.contains_key(module) || failed.contains(module) {
+                continue;
+            }
 
-            for import in &self.cur_imports {
-                let path = match module_type {
-                    ModuleType::Directory => {
-                        parse_path(&format!("{}/init", &self.cur_file), import)
+            match self.imports_memo.get(module) {
+                Some(imports) => {
+                    for import in imports {
+                        if seen.insert(import) {
+                            queue.push_back(import);
+                        }
                     }
-                    ModuleType::Lua => parse_path(&self.cur_file, import),
-                    _ => panic!("Unknown module type"),
-                };
+                }
+                None => pending.push(module.clone()),
+            }
+        }
+
+        pending
+    }
+
+    /// Visits a module if it isn't cached yet, returning the modules it imports
+    fn visit_module(&mut self, module: &str) -> Result<Vec<String>, Diagnostic> {
+        // If it's already visited, then we don't need to visit it again
+        if let Some(imports) = self.imports_memo.get(module) {
+            return Ok(imports.clone());
+        }
+
+        if self.all_json.contains_key(module) {
+            return Ok(Vec::new());
+        }
+
+        let loaded = self.load_module(module)?;
+        let imports = match &loaded {
+            LoadedModule::Lua(module) => module.imports.clone(),
+            LoadedModule::Json(_) => Vec::new(),
+        };
+
+        self.remember(module, loaded);
+        Ok(imports)
+    }
 
-                if !rel_imports_set.contains(&path) {
-                    rel_imports_set.insert(path.clone());
-                    rel_imports.push(path.clone());
+    /// Reads and transforms a module, or gets it from the cache. This doesn't change the state of
+    /// the visitor, so modules can be loaded from several threads
+    fn load_module(&self, module: &str) -> Result<LoadedModule, Diagnostic> {
+        // Get the module's file
+        let (module_path, module_type) = self.resolve(module)
+            .map_err(|err| Diagnostic::error("unresolved", err).with_module(module))?;
+
+        let error = |code: &'static str, message: String, line: Option<usize>| {
+            Diagnostic::error(code, message)
+                .with_module(module)
+                .with_file(&module_path)
+                .with_line(line)
+        };
+
+        let source = self
+            .fs
+            .read_to_string(Path::new(&module_path))
+            .map_err(|err| error("io", err.to_string(), None))?;
+
+        // The file as the cache knows it, which doesn't depend on where luajoin runs from
+        let cache_file = module_path.strip_prefix(self.src_dir.as_str()).unwrap_or(&module_path);
+
+        // Unless it was visited by a previous run
+        let cache = self.cache.as_ref().filter(|_| !self.all_branches);
+        if let Some(cached) = cache.and_then(|cache| cache.get(module, cache_file, &source))
+        {
+            return Ok(match module_type {
+                ModuleType::Json => LoadedModule::Json(cached.code),
+                _ => LoadedModule::Lua(cached),
+            });
+        }
+
+        let cached = match module_type {
+            ModuleType::Json => {
+                // Parse the json
+                let json: serde_json::Value = serde_json::from_str(&source)
+                    .map_err(|err| error("json", err.to_string(), Some(err.line())))?;
+
+                CachedModule {
+                    code: "return ".to_owned() + &json_to_lua(&json).to_string(),
+                    imports: Vec::new(),
+                    import_lines: HashMap::new(),
+                    dynamic_requires: Vec::new(),
                 }
+            }
+            _ => self.transform_module(module, &module_type, &source, error)?,
+        };
+
+        if let Some(cache) = cache {
+            cache.put(module, cache_file, &source, &cached);
+        }
+
+        Ok(match module_type {
+            ModuleType::Json => LoadedModule::Json(cached.code),
+            _ => LoadedModule::Lua(cached),
+        })
+    }
 
-                if !all_file_imports_set.contains(&path) {
-                    all_file_imports_set.insert(path.clone());
-                    all_file_imports.push(path.clone());
+    /// Parses a Lua module, to strip it and find its imports
+    fn transform_module(
+        &self,
+        module: &str,
+        module_type: &ModuleType,
+        source: &str,
+        error: impl Fn(&'static str, String, Option<usize>) -> Diagnostic,
+    ) -> Result<CachedModule, Diagnostic> {
+        let module_content = match self.all_branches {
+            true => keep_all_branches(source),
+            false => preprocess(source, &self.defines),
+        }
+        .map_err(|err| error("preprocess", err.message, Some(err.line)))?;
+        let module_ast = full_moon::parse(&module_content).map_err(|err| {
+            let line = match &err {
+                full_moon::Error::AstError(ast::AstError::UnexpectedToken { token, .. }) => {
+                    Some(token.start_position().line())
                 }
+                full_moon::Error::TokenizerError(err) => Some(err.position().line()),
+                _ => None,
+            };
+
+            error("parse", err.to_string(), line)
+        })?;
+
+        // Package sources follow Rojo, where the `script` of an init file is its directory
+        let mut visitor = ModuleVisitor {
+            directory: matches!(module_type, ModuleType::Directory)
+                && self.package_of(module).is_some(),
+            wally_folders: WALLY_FOLDERS
+                .iter()
+                .filter(|folder| self.packages.contains_key(**folder))
+                .map(|folder| folder.to_string())
+                .collect(),
+            ..Default::default()
+        };
+        let new_ast = visitor.visit_ast(module_ast);
+
+        // If there's errors, then we can't continue
+        if let Some((message, line)) = visitor.errors.first() {
+            return Err(error("require", message.clone(), Some(*line)));
+        }
+
+        // Parse all the relative imports
+        let mut rel_imports: Vec<String> = Vec::new();
+        let mut rel_imports_set: HashSet<String> = HashSet::new();
+        let mut import_lines: HashMap<String, usize> = HashMap::new();
+
+        for (import, line) in visitor.imports.iter().zip(&visitor.import_lines) {
+            let path = match module_type {
+                ModuleType::Directory => parse_path(&format!("{}/init", module), import),
+                ModuleType::Lua => parse_path(module, import),
+                _ => panic!("Unknown module type"),
+            };
+
+            import_lines.entry(path.clone()).or_insert(*line);
+
+            if rel_imports_set.insert(path.clone()) {
+                rel_imports.push(path);
             }
+        }
 
-            // Transform the AST
-            let new_source = full_moon::print(&new_ast);
+        // Transform the AST
+        Ok(CachedModule {
+            code: full_moon::print(&new_ast),
+            imports: rel_imports,
+            import_lines,
+            dynamic_requires: visitor.dynamic_requires,
+        })
+    }
 
-            self.transformed_memo
-                .insert(self.cur_file.clone(), new_source);
-            self.imports_memo.insert(self.cur_file.clone(), rel_imports);
+    fn remember(&mut self, module: &str, loaded: LoadedModule) {
+        let module = module.to_string();
 
-            i += 1;
+        let cached = match loaded {
+            LoadedModule::Json(lua) => {
+                self.all_json.insert(module, lua);
+                return;
+            }
+            LoadedModule::Lua(cached) => cached,
+        };
+
+        for import in &cached.imports {
+            self.importers_memo
+                .entry(import.clone())
+                .or_default()
+                .insert(module.clone());
         }
 
-        Ok(all_file_imports)
+        self.transformed_memo.insert(module.clone(), cached.code);
+        self.imports_memo.insert(module.clone(), cached.imports);
+        self.import_lines_memo
+            .insert(module.clone(), cached.import_lines);
+        self.dynamic_requires_memo
+            .insert(module, cached.dynamic_requires);
+    }
+}
+
+/// The result of a traversal, kept to be reused while the imports don't change
+struct Traversal {
+    roots: Vec<String>,
+    modules: Vec<String>,
+    reachable: HashSet<String>, // the same modules, to look them up
+}
+
+/// A module that was read, before it's added to the memos
+enum LoadedModule {
+    Lua(CachedModule),
+    Json(String), // the lua table of the json
+}
+
+/// Strips a single module, and collects its requires
+#[derive(Default)]
+struct ModuleVisitor {
+    imports: Vec<String>, // as a relative path, from the module, so like ./../hello/.., without extension
+    import_lines: Vec<usize>, // the line of each of the imports
+    dynamic_requires: Vec<usize>, // the lines of the requires with a computed path
+    errors: Vec<(String, usize)>, // with their line
+    directory: bool, // whether the module is an init file of a package, whose `script` is its directory
+    wally_folders: Vec<String>, // the Wally folders there are packages for
+}
+
+impl ModuleVisitor {
+    /// The import an instance path leads to, like `script.Parent.util` relative to the module, or
+    /// `ReplicatedStorage.Packages.Promise` into a folder Wally installed packages in. Others are
+    /// left for the runtime
+    fn instance_path(&self, names: &[String]) -> Option<String> {
+        if names.first()? != "script" {
+            let start = names
+                .iter()
+                .position(|name| self.wally_folders.contains(name))?;
+
+            return (start + 1 < names.len()).then(|| names[start..].join("/"));
+        }
+
+        // An init file is in its directory, so it's one more level up
+        let mut path: Vec<&str> = match self.directory {
+            true => vec!["."],
+            false => Vec::new(),
+        };
+
+        path.extend(names[1..].iter().map(|name| match name.as_str() {
+            "Parent" => ".",
+            name => name,
+        }));
+
+        Some(path.join("/"))
     }
 }
 
@@ -387,7 +1064,7 @@ fn empty_token_ref(lines: usize) -> TokenReference {
     TokenReference::new(Vec::new(), empty_token(lines), Vec::new())
 }
 
-impl<'a> VisitorMut for RequireVisitor<'a> {
+impl VisitorMut for ModuleVisitor {
     // Remove every comment from the AST
     fn visit_multi_line_comment(&mut self, token: Token) -> Token {
         empty_token(token.to_string().split("\n").count() - 1)
@@ -433,6 +1110,8 @@ impl<'a> VisitorMut for RequireVisitor<'a> {
             }
         }
 
+        let line = node.start_position().map_or(0, |position| position.line());
+
         // Get the arguments
         if let ast::Suffix::Call(ast::Call::AnonymousCall(ast::FunctionArgs::Parentheses {
             parentheses,
@@ -442,8 +1121,10 @@ impl<'a> VisitorMut for RequireVisitor<'a> {
             let first_arg = match arguments.iter().next() {
                 Some(arg) => arg,
                 None => {
-                    self.cur_errors
-                        .push(String::from("An argument is required for '_require'"));
+                    self.errors.push((
+                        String::from("An argument is required for '_require'"),
+                        line,
+                    ));
 
                     return node.clone();
                 }
@@ -467,53 +1148,29 @@ impl<'a> VisitorMut for RequireVisitor<'a> {
                             let required_path = literal.to_string();
 
                             // Add it to the imports
-                            self.cur_imports.push(required_path.clone());
+                            self.imports.push(required_path.clone());
+                            self.import_lines.push(line);
                         }
                     }
-                    // Here, it's going to be a require call with a 'script' inside
+                    // Here, it's going to be a require call with a 'script' or a Wally folder inside
                     ast::Value::Var(Var::Expression(ve)) => {
-                        let parts = ve.tokens().into_iter();
-
-                        // Will store the state for the relative import, later joined into a string
-                        let mut rel_import_path: Vec<String> = Vec::new();
-
-                        for (i, part) in parts.enumerate() {
-                            let part_str = match part.token_type() {
-                                TokenType::Identifier { identifier } => {
-                                    Some(identifier.to_string())
-                                }
-                                TokenType::StringLiteral {
-                                    literal,
-                                    multi_line: _,
-                                    quote_type: _,
-                                } => Some(literal.to_string()),
+                        let names: Vec<String> = ve
+                            .tokens()
+                            .filter_map(|part| match part.token_type() {
+                                TokenType::Identifier { identifier } => Some(identifier.to_string()),
+                                TokenType::StringLiteral { literal, .. } => Some(literal.to_string()),
                                 _ => None,
-                            };
-
-                            if let Some(part_str) = part_str {
-                                // Make sure the first part is 'script'
-                                if i == 0 && part_str != "script" {
-                                    return node.clone();
-                                }
-
-                                match part_str.as_str() {
-                                    "script" => {
-                                        // Do nothing
-                                    }
-                                    "Parent" => {
-                                        rel_import_path.push(String::from("."));
-                                    }
-                                    _ => {
-                                        rel_import_path.push(part_str);
-                                    }
-                                }
-                            }
-                        }
+                            })
+                            .collect();
 
                         // Get the new path
-                        let required_path = rel_import_path.join("/");
-                        println!("{}", required_path);
-                        self.cur_imports.push(required_path.clone());
+                        let required_path = match self.instance_path(&names) {
+                            Some(path) => path,
+                            None => return node.clone(),
+                        };
+
+                        self.imports.push(required_path.clone());
+                        self.import_lines.push(line);
 
                         // Create the tree nodes
                         let mut punctuated = Punctuated::new();
@@ -586,11 +1243,197 @@ impl<'a> VisitorMut for RequireVisitor<'a> {
 
                         // TODO: the token into a _require
                     }
-                    _ => (),
+                    // Names, calls and such are only known at runtime
+                    _ => self.dynamic_requires.push(line),
                 };
+            } else {
+                self.dynamic_requires.push(line);
+            }
+        }
+
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryFs;
+    use std::fs;
+
+    #[test]
+    fn parallel_traversal_matches_serial() {
+        let dir = std::env::temp_dir().join(format!("luajoin-parser-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.lua"), "local a = require(\"./lib\")\nrequire(\"./b\")").unwrap();
+        fs::write(dir.join("lib/init.lua"), "return require(\"./c\") and require(\"../b\")").unwrap();
+        fs::write(dir.join("lib/c.lua"), "return require(\"../data\")").unwrap();
+        fs::write(dir.join("b.lua"), "-- b\nreturn 1").unwrap();
+        fs::write(dir.join("data.json"), "{\"a\": [1, 2]}").unwrap();
+
+        let src_dir = dir.to_string_lossy().to_string();
+        let bundle = |jobs: usize| {
+            RequireVisitor::new(&src_dir, "main", false, BTreeMap::new())
+                .with_jobs(jobs)
+                .generate_bundle(&[])
+                .unwrap()
+        };
+
+        let serial = bundle(1);
+        assert_eq!(serial.modules, vec!["main", "lib", "b", "lib/c", "data"]);
+        assert_eq!(serial.source_map.files[0], "[BUNDLER]");
+        assert_eq!(bundle(4), serial);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn incremental_rebuilds_match_fresh_ones() {
+        let dir = std::env::temp_dir().join(format!("luajoin-rebuild-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.lua"), "return require(\"./a\")").unwrap();
+        fs::write(dir.join("a.lua"), "return require(\"./b\")").unwrap();
+        fs::write(dir.join("b.lua"), "return 1").unwrap();
+
+        let src_dir = dir.to_string_lossy().to_string();
+        let fresh = || {
+            RequireVisitor::new(&src_dir, "main", false, BTreeMap::new())
+                .generate_bundle(&[])
+                .unwrap()
+        };
+
+        let mut visitor = RequireVisitor::new(&src_dir, "main", false, BTreeMap::new());
+        visitor.generate_bundle(&[]).unwrap();
+
+        // Only the code changes
+        fs::write(dir.join("b.lua"), "return 2").unwrap();
+        visitor.mark_file_change("b");
+        assert_eq!(visitor.generate_bundle(&[]).unwrap(), fresh());
+
+        // `b` isn't imported anymore, so it's forgotten
+        fs::write(dir.join("a.lua"), "return 3").unwrap();
+        visitor.mark_file_change("a");
+        assert_eq!(visitor.generate_bundle(&[]).unwrap(), fresh());
+        assert_eq!(visitor.module_code("b"), None);
+
+        fs::write(dir.join("a.lua"), "return require(\"./b\")").unwrap();
+        visitor.mark_file_change("a");
+        assert_eq!(visitor.generate_bundle(&[]).unwrap(), fresh());
+        assert_eq!(visitor.importers("b"), vec!["a"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_wally_packages() {
+        let index = "Packages/_Index";
+        let memory_fs: MemoryFs = [
+            (
+                String::from("src/main.lua"),
+                "local ReplicatedStorage = game:GetService(\"ReplicatedStorage\")\n\
+                 return require(ReplicatedStorage.Packages.Promise)",
+            ),
+            (
+                String::from("Packages/Promise.lua"),
+                "return require(script.Parent._Index[\"evaera_promise@4.0.0\"][\"promise\"])",
+            ),
+            (
+                format!("{}/evaera_promise@4.0.0/promise/default.project.json", index),
+                "{ \"name\": \"promise\", \"tree\": { \"$path\": \"lib\" } }",
+            ),
+            (
+                format!("{}/evaera_promise@4.0.0/promise/lib/init.lua", index),
+                "return require(script.Util) and require(script.Parent.t)",
+            ),
+            (format!("{}/evaera_promise@4.0.0/promise/lib/Util.lua", index), "return {}"),
+            (
+                format!("{}/evaera_promise@4.0.0/t.lua", index),
+                "return require(script.Parent.Parent[\"osyris_t@3.0.0\"][\"t\"])",
+            ),
+            (format!("{}/osyris_t@3.0.0/t/init.lua", index), "return {}"),
+        ]
+        .into_iter()
+        .collect();
+
+        let packages = [Package {
+            name: String::from("Packages"),
+            path: String::from("Packages"),
+            src_dir: "Packages".into(),
+            entry_file: String::from("init"),
+            archive: None,
+        }];
+
+        let output = RequireVisitor::new("src", "main", false, BTreeMap::new())
+            .with_fs(Arc::new(memory_fs))
+            .with_packages(&packages)
+            .generate_bundle(&[])
+            .unwrap();
+
+        let promise = "Packages/_Index/evaera_promise@4.0.0/promise";
+        assert_eq!(
+            output.modules,
+            vec![
+                String::from("main"),
+                String::from("Packages/Promise"),
+                String::from(promise),
+                format!("{}/Util", promise),
+                String::from("Packages/_Index/evaera_promise@4.0.0/t"),
+                String::from("Packages/_Index/osyris_t@3.0.0/t"),
+            ]
+        );
+        assert!(output.code.contains("_require(\"Packages/Promise\")"));
+        assert!(output.code.contains(&format!("__LUAJOIN_DIRECTORIES[\"{}\"]=true", promise)));
+    }
+
+    #[test]
+    fn rejects_imports_of_dev_modules() {
+        let bundle = |main: &str| {
+            let memory_fs: MemoryFs = [
+                ("src/main.lua", main),
+                ("src/.dev.lua", "return require(\"./util\") and require(\"./.dev/inspector\")"),
+                ("src/.dev/inspector.lua", "return {}"),
+                ("src/util.lua", "return {}"),
+            ]
+            .into_iter()
+            .collect();
+
+            RequireVisitor::new("src", "main", false, BTreeMap::new())
+                .with_fs(Arc::new(memory_fs))
+                .generate_bundle(&[String::from(".dev")])
+        };
+
+        match bundle("return require(\"./.dev/inspector\")") {
+            Err(BundleError::DevImport { importer, module }) => {
+                assert_eq!((importer.as_str(), module.as_str()), ("main", ".dev/inspector"))
             }
+            other => panic!("expected a development import, got {:?}", other.map(|o| o.modules)),
         }
 
-        return node;
+        // Both the entry file and the development modules may require a module
+        let output = bundle("return require(\"./util\")").unwrap();
+        assert_eq!(output.modules, vec!["main", "util"]);
+    }
+
+    #[test]
+    fn leaves_instance_paths_outside_packages() {
+        let memory_fs: MemoryFs = [
+            (
+                "src/main.lua",
+                "local ReplicatedStorage = game:GetService(\"ReplicatedStorage\")\n\
+                 return require(\"./lib\") and require(ReplicatedStorage.Packages.Promise)",
+            ),
+            ("src/lib/init.lua", "return require(script.Parent.x)"),
+            ("src/lib/x.lua", "return {}"),
+        ]
+        .into_iter()
+        .collect();
+
+        let output = RequireVisitor::new("src", "main", false, BTreeMap::new())
+            .with_fs(Arc::new(memory_fs))
+            .generate_bundle(&[])
+            .unwrap();
+
+        assert_eq!(output.modules, vec!["main", "lib", "lib/x"]);
+        assert!(output.code.contains("require(ReplicatedStorage.Packages.Promise)"));
     }
 }
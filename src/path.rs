@@ -28,7 +28,41 @@ pub fn parse_path(cur_path: &str, next_path: &str) -> String {
         }
     }
 
-    return new_path.join("/");
+    new_path.join("/")
+}
+
+/// Whether a path (using `/`) matches a glob pattern. `*` and `?` match within a single
+/// component, `**` matches any number of components
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_start_matches("./").split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path_rest)) => {
+                let pattern: Vec<char> = first.chars().collect();
+                let component: Vec<char> = component.chars().collect();
+
+                match_chars(&pattern, &component) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| match_chars(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && match_chars(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && match_chars(rest, &text[1..]),
+    }
 }
 
 #[cfg(test)]
@@ -58,4 +92,14 @@ mod tests {
 
         assert_eq!(parse_path(cur_path, next_path), "dir/other");
     }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("**/*.spec.lua", "ui/button.spec.lua"));
+        assert!(glob_match("**/*.spec.lua", "main.spec.lua"));
+        assert!(glob_match("legacy/**", "legacy/old/a.lua"));
+        assert!(glob_match("util/?.lua", "util/a.lua"));
+        assert!(!glob_match("*.lua", "ui/button.lua"));
+        assert!(!glob_match("util/?.lua", "util/ab.lua"));
+    }
 }
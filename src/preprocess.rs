@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A directive that can't be evaluated, and its line
+#[derive(Debug)]
+pub struct PreprocessError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// An `--#if` block that is still open
+struct Block {
+    line: usize,
+    parent_active: bool, // whether the code around the block is enabled
+    taken: bool,         // whether one of the branches was enabled already
+    active: bool,        // whether the current branch is enabled
+    has_else: bool,
+}
+
+/// Handles the `--#if`, `--#elseif`, `--#else` and `--#endif` directives, whose conditions are
+/// evaluated against the defines. The lines of disabled branches are blanked rather than removed,
+/// so the line numbers of the module don't change
+pub fn preprocess(
+    source: &str,
+    defines: &BTreeMap<String, bool>,
+) -> Result<String, PreprocessError> {
+    process(source, defines, false)
+}
+
+/// Same as `preprocess`, but every branch is kept, as it is enabled by some defines. Only the
+/// directives are blanked
+pub fn keep_all_branches(source: &str) -> Result<String, PreprocessError> {
+    process(source, &BTreeMap::new(), true)
+}
+
+fn process(
+    source: &str,
+    defines: &BTreeMap<String, bool>,
+    all_branches: bool,
+) -> Result<String, PreprocessError> {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+
+    for (i, line) in source.split('\n').enumerate() {
+        let line_number = i + 1;
+        let active = blocks.last().is_none_or(|block| block.active);
+
+        let directive = match line.trim().strip_prefix("--#") {
+            Some(directive) => directive.trim(),
+            None => {
+                lines.push(if active { line } else { "" });
+                continue;
+            }
+        };
+
+        let (keyword, condition) = match directive.split_once(char::is_whitespace) {
+            Some((keyword, condition)) => (keyword, condition.trim()),
+            None => (directive, ""),
+        };
+
+        let error = |message: &str| PreprocessError {
+            line: line_number,
+            message: message.to_string(),
+        };
+
+        match keyword {
+            "if" => {
+                let value = evaluate(condition, defines).map_err(|err| error(&err))?;
+
+                blocks.push(Block {
+                    line: line_number,
+                    parent_active: active,
+                    taken: active && value,
+                    active: active && (value || all_branches),
+                    has_else: false,
+                });
+            }
+            "elseif" => {
+                let value = evaluate(condition, defines).map_err(|err| error(&err))?;
+                let block = match blocks.last_mut() {
+                    Some(block) if !block.has_else => block,
+                    Some(_) => return Err(error("'--#elseif' after '--#else'")),
+                    None => return Err(error("'--#elseif' without a matching '--#if'")),
+                };
+
+                block.active = block.parent_active && ((!block.taken && value) || all_branches);
+                block.taken = block.taken || block.active;
+            }
+            "else" | "endif" if !condition.is_empty() => {
+                return Err(error(&format!("'--#{}' does not take a condition", keyword)));
+            }
+            "else" => {
+                let block = match blocks.last_mut() {
+                    Some(block) if !block.has_else => block,
+                    Some(_) => return Err(error("'--#else' after '--#else'")),
+                    None => return Err(error("'--#else' without a matching '--#if'")),
+                };
+
+                block.active = block.parent_active && (!block.taken || all_branches);
+                block.taken = true;
+                block.has_else = true;
+            }
+            "endif" => {
+                if blocks.pop().is_none() {
+                    return Err(error("'--#endif' without a matching '--#if'"));
+                }
+            }
+            // Any other comment starting with `--#` is kept as is
+            _ => {
+                lines.push(if active { line } else { "" });
+                continue;
+            }
+        }
+
+        lines.push("");
+    }
+
+    if let Some(block) = blocks.last() {
+        return Err(PreprocessError {
+            line: block.line,
+            message: String::from("'--#if' is never closed"),
+        });
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// Evaluates a condition made of defines, `not`, `and`, `or` and parentheses.
+/// Names that are not defined are false
+fn evaluate(condition: &str, defines: &BTreeMap<String, bool>) -> Result<bool, String> {
+    let spaced = condition.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+
+    if tokens.is_empty() {
+        return Err(String::from("missing condition"));
+    }
+
+    let mut parser = ConditionParser {
+        tokens,
+        pos: 0,
+        defines,
+    };
+
+    let value = parser.parse_or()?;
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(format!("unexpected '{}' in condition", token)),
+        None => Ok(value),
+    }
+}
+
+struct ConditionParser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    defines: &'a BTreeMap<String, bool>,
+}
+
+impl<'a> ConditionParser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn accept(&mut self, expected: &str) -> bool {
+        if self.tokens.get(self.pos) == Some(&expected) {
+            self.pos += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let mut value = self.parse_and()?;
+        while self.accept("or") {
+            value |= self.parse_and()?;
+        }
+
+        Ok(value)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let mut value = self.parse_not()?;
+        while self.accept("and") {
+            value &= self.parse_not()?;
+        }
+
+        Ok(value)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.accept("not") {
+            return Ok(!self.parse_not()?);
+        }
+
+        match self.next() {
+            Some("(") => {
+                let value = self.parse_or()?;
+                if !self.accept(")") {
+                    return Err(String::from("missing ')' in condition"));
+                }
+
+                Ok(value)
+            }
+            Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(token @ (")" | "and" | "or")) => Err(format!("unexpected '{}' in condition", token)),
+            Some(name) => Ok(self.defines.get(name).copied().unwrap_or(false)),
+            None => Err(String::from("unexpected end of condition")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines(names: &[(&str, bool)]) -> BTreeMap<String, bool> {
+        names
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    const SOURCE: &str = "local a = 1\n--#if dev\nprint(a)\n--#endif\nreturn a";
+
+    #[test]
+    fn blanks_disabled_blocks() {
+        assert_eq!(
+            preprocess(SOURCE, &defines(&[])).unwrap(),
+            "local a = 1\n\n\n\nreturn a"
+        );
+
+        assert_eq!(
+            preprocess(SOURCE, &defines(&[("dev", true)])).unwrap(),
+            "local a = 1\n\nprint(a)\n\nreturn a"
+        );
+    }
+
+    #[test]
+    fn picks_the_first_enabled_branch() {
+        let source = "--#if ROBLOX\na\n--#elseif LUAU and not dev\nb\n--#else\nc\n--#endif";
+
+        assert_eq!(
+            preprocess(source, &defines(&[("ROBLOX", true), ("LUAU", true)])).unwrap(),
+            "\na\n\n\n\n\n"
+        );
+        assert_eq!(
+            preprocess(source, &defines(&[("LUAU", true)])).unwrap(),
+            "\n\n\nb\n\n\n"
+        );
+        assert_eq!(
+            preprocess(source, &defines(&[("LUAU", true), ("dev", true)])).unwrap(),
+            "\n\n\n\n\nc\n"
+        );
+    }
+
+    #[test]
+    fn nested_blocks_follow_their_parent() {
+        let source = "--#if A\n--#if B\nx\n--#else\ny\n--#endif\n--#endif";
+
+        assert_eq!(
+            preprocess(source, &defines(&[("B", true)])).unwrap(),
+            "\n\n\n\n\n\n"
+        );
+    }
+
+    #[test]
+    fn keeps_all_branches() {
+        let source = "--#if ROBLOX\na\n--#elseif LUAU\nb\n--#else\nc\n--#endif";
+
+        assert_eq!(keep_all_branches(source).unwrap(), "\na\n\nb\n\nc\n");
+        assert!(keep_all_branches("--#if A and\na\n--#endif").is_err());
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let defines = defines(&[("A", true), ("B", false)]);
+
+        assert_eq!(evaluate("A and (B or not B)", &defines), Ok(true));
+        assert_eq!(evaluate("not A or B", &defines), Ok(false));
+        assert!(evaluate("A and", &defines).is_err());
+        assert!(evaluate("(A", &defines).is_err());
+    }
+
+    #[test]
+    fn rejects_unbalanced_directives() {
+        assert!(preprocess("--#if dev\nprint(1)", &defines(&[])).is_err());
+        assert!(preprocess("print(1)\n--#endif", &defines(&[])).is_err());
+        assert!(preprocess("--#if A\n--#else\n--#elseif B\n--#endif", &defines(&[])).is_err());
+    }
+}
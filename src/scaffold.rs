@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A single change to the file system
+pub enum Step {
+    CreateDir(PathBuf),
+    CreateFile(PathBuf, String),
+    AppendFile(PathBuf, String),
+    Keep(PathBuf),
+}
+
+/// A change that was applied, and how to undo it
+enum Applied {
+    CreatedDir(PathBuf),
+    CreatedFile(PathBuf),
+    AppendedFile(PathBuf, Vec<u8>),
+}
+
+/// An ordered list of changes, which are either all applied or not at all
+#[derive(Default)]
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a directory and its missing parents, keeping it if it's already there
+    pub fn create_dir(&mut self, path: &Path) {
+        if path.is_dir() {
+            self.steps.push(Step::Keep(path.to_path_buf()));
+            return;
+        }
+
+        self.create_missing_dirs(path);
+    }
+
+    fn create_missing_dirs(&mut self, path: &Path) {
+        let mut missing = Vec::new();
+        let mut cur = Some(path);
+
+        while let Some(dir) = cur {
+            if dir.as_os_str().is_empty() || dir.exists() || self.creates(dir) {
+                break;
+            }
+
+            missing.push(dir.to_path_buf());
+            cur = dir.parent();
+        }
+
+        // Create the outermost directories first
+        for dir in missing.into_iter().rev() {
+            self.steps.push(Step::CreateDir(dir));
+        }
+    }
+
+    /// Creates a file, keeping it as is if it already exists
+    pub fn create_file(&mut self, path: &Path, content: String) {
+        if path.exists() {
+            self.steps.push(Step::Keep(path.to_path_buf()));
+            return;
+        }
+
+        if let Some(parent) = path.parent() {
+            self.create_missing_dirs(parent);
+        }
+
+        self.steps.push(Step::CreateFile(path.to_path_buf(), content));
+    }
+
+    /// Makes sure a file contains every given line, appending the missing ones
+    pub fn ensure_lines(&mut self, path: &Path, lines: &[String]) -> Result<(), Box<dyn Error>> {
+        if !path.exists() {
+            self.create_file(path, lines.join("\n") + "\n");
+            return Ok(());
+        }
+
+        let existing = fs::read_to_string(path)?;
+        let missing = missing_lines(&existing, lines);
+
+        if missing.is_empty() {
+            self.steps.push(Step::Keep(path.to_path_buf()));
+            return Ok(());
+        }
+
+        // Make sure the appended lines don't end up on the last existing line
+        let mut content = String::new();
+        if !existing.is_empty() && !existing.ends_with('\n') {
+            content.push('\n');
+        }
+
+        content.push_str(&(missing.join("\n") + "\n"));
+        self.steps.push(Step::AppendFile(path.to_path_buf(), content));
+
+        Ok(())
+    }
+
+    /// Describes every step, in the order they would be applied
+    pub fn describe(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                Step::CreateDir(path) => format!("create directory '{}'", path.display()),
+                Step::CreateFile(path, _) => format!("create '{}'", path.display()),
+                Step::AppendFile(path, content) => format!(
+                    "append {} line(s) to '{}'",
+                    content.trim().lines().count(),
+                    path.display()
+                ),
+                Step::Keep(path) => format!("keep existing '{}'", path.display()),
+            })
+            .collect()
+    }
+
+    /// Applies every step, undoing the applied ones if any of them fails
+    pub fn apply(&self) -> Result<(), Box<dyn Error>> {
+        let mut applied = Vec::new();
+
+        for step in &self.steps {
+            if let Err(err) = apply_step(step, &mut applied) {
+                rollback(applied);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn creates(&self, path: &Path) -> bool {
+        self.steps
+            .iter()
+            .any(|step| matches!(step, Step::CreateDir(dir) if dir == path))
+    }
+}
+
+fn apply_step(step: &Step, applied: &mut Vec<Applied>) -> Result<(), Box<dyn Error>> {
+    match step {
+        Step::CreateDir(path) => {
+            fs::create_dir(path)
+                .map_err(|err| format!("Could not create '{}': {}", path.display(), err))?;
+            applied.push(Applied::CreatedDir(path.clone()));
+        }
+        Step::CreateFile(path, content) => {
+            // Never overwrite a file that appeared since the plan was made
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(|err| format!("Could not create '{}': {}", path.display(), err))?;
+            applied.push(Applied::CreatedFile(path.clone()));
+
+            file.write_all(content.as_bytes())?;
+        }
+        Step::AppendFile(path, content) => {
+            let original = fs::read(path)?;
+            let mut file = OpenOptions::new().append(true).open(path)?;
+            applied.push(Applied::AppendedFile(path.clone(), original));
+
+            file.write_all(content.as_bytes())?;
+        }
+        Step::Keep(_) => (),
+    }
+
+    Ok(())
+}
+
+fn rollback(applied: Vec<Applied>) {
+    // Undo in the reverse order, so files are removed before their directories
+    for change in applied.into_iter().rev() {
+        let _ = match change {
+            Applied::CreatedDir(path) => fs::remove_dir(path),
+            Applied::CreatedFile(path) => fs::remove_file(path),
+            Applied::AppendedFile(path, original) => fs::write(path, original),
+        };
+    }
+}
+
+/// Gets the lines that are not in the content yet, ignoring surrounding whitespace
+fn missing_lines(content: &str, lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .filter(|line| !content.lines().any(|existing| existing.trim() == line.trim()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_missing_lines() {
+        let lines = vec![String::from("/out"), String::from("/src/.dev.lua")];
+
+        assert_eq!(missing_lines("target/\n/out  \n", &lines), vec!["/src/.dev.lua"]);
+        assert!(missing_lines("/src/.dev.lua\n/out", &lines).is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::build::minify;
+use crate::bundler::{BundleOutput, Bundler};
+use crate::config::Budgets;
+use crate::path::glob_match;
+
+/// The sizes in bytes of a module: its source file, its code once transformed by the bundler, and
+/// that code minified
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ModuleSize {
+    pub module: String,
+    pub raw: usize,
+    pub transformed: usize,
+    pub minified: usize,
+}
+
+/// The sizes of a bundle and of each of its modules
+#[derive(Serialize, Deserialize)]
+pub struct SizeReport {
+    pub bundle: usize,
+    pub minified: bool, // whether the bundle is made of the minified code
+    pub modules: Vec<ModuleSize>,
+}
+
+impl SizeReport {
+    /// Measures every module of a bundle
+    pub fn measure(bundler: &Bundler, output: &BundleOutput) -> Self {
+        let modules = output
+            .modules
+            .iter()
+            .map(|module| {
+                let raw = bundler
+                    .module_file(module)
+                    .and_then(|file| bundler.visitor().fs().read_to_string(Path::new(&file)).ok())
+                    .map_or(0, |source| source.len());
+
+                let code = bundler.visitor().module_code(module).unwrap_or_default();
+                let minified = minify(code).map_or(code.len(), |minified| minified.len());
+
+                ModuleSize {
+                    module: module.clone(),
+                    raw,
+                    transformed: code.len(),
+                    minified,
+                }
+            })
+            .collect();
+
+        Self {
+            bundle: output.code.len(),
+            minified: bundler.options().minify,
+            modules,
+        }
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The size a module takes in the bundle
+    fn bundled(&self, module: &ModuleSize) -> usize {
+        if self.minified {
+            module.minified
+        } else {
+            module.transformed
+        }
+    }
+
+    /// The modules, largest first
+    fn sorted_modules(&self) -> Vec<&ModuleSize> {
+        let mut modules: Vec<&ModuleSize> = self.modules.iter().collect();
+        modules.sort_by(|a, b| {
+            self.bundled(b)
+                .cmp(&self.bundled(a))
+                .then_with(|| a.module.cmp(&b.module))
+        });
+
+        modules
+    }
+
+    fn share(&self, module: &ModuleSize) -> f64 {
+        if self.bundle == 0 {
+            return 0.0;
+        }
+
+        self.bundled(module) as f64 * 100.0 / self.bundle as f64
+    }
+
+    /// How much each module grew since the previous report, new modules grew from nothing
+    fn deltas(&self, previous: &SizeReport) -> HashMap<&str, i64> {
+        let previous_sizes: HashMap<&str, usize> = previous
+            .modules
+            .iter()
+            .map(|module| (module.module.as_str(), previous.bundled(module)))
+            .collect();
+
+        self.modules
+            .iter()
+            .map(|module| {
+                let before = previous_sizes.get(module.module.as_str()).copied().unwrap_or(0);
+                (module.module.as_str(), self.bundled(module) as i64 - before as i64)
+            })
+            .collect()
+    }
+
+    /// Formats the report as a table, with the change since the previous report if there's one
+    pub fn to_table(&self, previous: Option<&SizeReport>) -> String {
+        let deltas = previous.map(|previous| self.deltas(previous));
+        let mut rows = vec![vec![
+            String::from("Module"),
+            String::from("Raw"),
+            String::from("Transformed"),
+            String::from("Minified"),
+            String::from("Share"),
+        ]];
+
+        if deltas.is_some() {
+            rows[0].push(String::from("Change"));
+        }
+
+        for module in self.sorted_modules() {
+            let mut row = vec![
+                module.module.clone(),
+                module.raw.to_string(),
+                module.transformed.to_string(),
+                module.minified.to_string(),
+                format!("{:.1}%", self.share(module)),
+            ];
+
+            if let Some(deltas) = &deltas {
+                row.push(format_delta(deltas[module.module.as_str()]));
+            }
+
+            rows.push(row);
+        }
+
+        let mut total = vec![
+            String::from("Bundle"),
+            String::new(),
+            String::new(),
+            String::new(),
+            self.bundle.to_string(),
+        ];
+
+        if let Some(previous) = previous {
+            total.push(format_delta(self.bundle as i64 - previous.bundle as i64));
+        }
+
+        rows.push(total);
+
+        // Left align the names, right align the numbers
+        let widths: Vec<usize> = (0..rows[0].len())
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect();
+
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(column, cell)| match column {
+                        0 => format!("{:<width$}", cell, width = widths[column]),
+                        _ => format!("{:>width$}", cell, width = widths[column]),
+                    })
+                    .collect::<Vec<String>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Formats the report as JSON, with the change since the previous report if there's one
+    pub fn to_json(&self, previous: Option<&SizeReport>) -> String {
+        let deltas = previous.map(|previous| self.deltas(previous));
+
+        let modules: Vec<serde_json::Value> = self
+            .sorted_modules()
+            .into_iter()
+            .map(|module| {
+                let mut value = json!({
+                    "module": module.module,
+                    "raw": module.raw,
+                    "transformed": module.transformed,
+                    "minified": module.minified,
+                    "share": self.share(module),
+                });
+
+                if let Some(deltas) = &deltas {
+                    value["change"] = json!(deltas[module.module.as_str()]);
+                }
+
+                value
+            })
+            .collect();
+
+        let mut value = json!({
+            "bundle": self.bundle,
+            "minified": self.minified,
+            "modules": modules,
+        });
+
+        if let Some(previous) = previous {
+            value["change"] = json!(self.bundle as i64 - previous.bundle as i64);
+
+            // Modules that were removed don't show up otherwise
+            let removed: Vec<&String> = previous
+                .modules
+                .iter()
+                .filter(|old| !self.modules.iter().any(|module| module.module == old.module))
+                .map(|old| &old.module)
+                .collect();
+
+            value["removed"] = json!(removed);
+        }
+
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+
+    /// Describes every budget that is exceeded
+    pub fn exceeded_budgets(&self, budgets: &Budgets) -> Vec<String> {
+        let mut exceeded = Vec::new();
+
+        if let Some(budget) = budgets.bundle {
+            if self.bundle > budget {
+                exceeded.push(format!(
+                    "The bundle is {} bytes, over its budget of {} bytes",
+                    self.bundle, budget
+                ));
+            }
+        }
+
+        for module in &self.modules {
+            let size = self.bundled(module);
+
+            for (pattern, budget) in &budgets.modules {
+                if size > *budget && glob_match(pattern, &module.module) {
+                    exceeded.push(format!(
+                        "Module '{}' is {} bytes, over the budget of {} bytes for '{}'",
+                        module.module, size, budget, pattern
+                    ));
+                }
+            }
+        }
+
+        exceeded
+    }
+}
+
+fn format_delta(delta: i64) -> String {
+    match delta {
+        0 => String::from("="),
+        delta if delta > 0 => format!("+{}", delta),
+        delta => delta.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn report(bundle: usize, modules: &[(&str, usize)]) -> SizeReport {
+        SizeReport {
+            bundle,
+            minified: true,
+            modules: modules
+                .iter()
+                .map(|(module, size)| ModuleSize {
+                    module: module.to_string(),
+                    raw: size * 2,
+                    transformed: size + 10,
+                    minified: *size,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn reports_exceeded_budgets() {
+        let report = report(500, &[("main", 100), ("ui/button", 300)]);
+        let budgets = Budgets {
+            bundle: Some(400),
+            modules: BTreeMap::from([(String::from("ui/**"), 200)]),
+        };
+
+        let exceeded = report.exceeded_budgets(&budgets);
+        assert_eq!(exceeded.len(), 2);
+        assert!(exceeded[1].contains("ui/button"));
+    }
+
+    #[test]
+    fn diffs_against_the_previous_report() {
+        let previous = report(300, &[("main", 100), ("old", 50)]);
+        let report = report(450, &[("main", 150), ("new", 200)]);
+
+        let deltas = report.deltas(&previous);
+        assert_eq!(deltas["main"], 50);
+        assert_eq!(deltas["new"], 200);
+        assert!(report.to_table(Some(&previous)).contains("+150"));
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const DEV_FILE_CONTENT: &str = include_str!("lua/.dev.lua");
+
+/// The names of the built-in templates, the first one being the default
+pub const BUILTIN_TEMPLATES: [&str; 4] = ["script", "roblox", "library", "multi"];
+
+/// A set of files to create in the source directory, whose paths and contents
+/// may contain `{{variable}}` placeholders
+pub struct Template {
+    files: Vec<(String, String)>,
+}
+
+impl Template {
+    /// Gets a built-in template by name
+    pub fn builtin(name: &str) -> Option<Self> {
+        let files: Vec<(&str, &str)> = match name {
+            "script" => vec![(
+                "{{entry}}.lua",
+                include_str!("lua/templates/script/main.lua"),
+            )],
+            "roblox" => vec![(
+                "{{entry}}.lua",
+                include_str!("lua/templates/roblox/main.lua"),
+            )],
+            "library" => vec![(
+                "{{entry}}.lua",
+                include_str!("lua/templates/library/main.lua"),
+            )],
+            "multi" => vec![
+                ("{{entry}}.lua", include_str!("lua/templates/multi/main.lua")),
+                (
+                    "platform/init.lua",
+                    include_str!("lua/templates/multi/platform/init.lua"),
+                ),
+                (
+                    "platform/roblox.lua",
+                    include_str!("lua/templates/multi/platform/roblox.lua"),
+                ),
+                (
+                    "platform/lua.lua",
+                    include_str!("lua/templates/multi/platform/lua.lua"),
+                ),
+            ],
+            _ => return None,
+        };
+
+        // Every built-in template comes with the development file
+        let mut files: Vec<(String, String)> = files
+            .into_iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect();
+
+        files.push((".dev.lua".to_string(), DEV_FILE_CONTENT.to_string()));
+
+        Some(Self { files })
+    }
+
+    /// Reads every file of a local directory as a template
+    pub fn from_dir(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut files = Vec::new();
+        read_dir_files(dir, dir, &mut files)?;
+
+        // Keep the file order stable, whatever the file system returns
+        files.sort();
+
+        Ok(Self { files })
+    }
+
+    /// Gets a template from either a built-in name or a path to a local directory
+    pub fn load(name: &str) -> Result<Self, Box<dyn Error>> {
+        if let Some(template) = Self::builtin(name) {
+            return Ok(template);
+        }
+
+        let dir = Path::new(name);
+        if dir.is_dir() {
+            return Self::from_dir(dir);
+        }
+
+        Err(format!(
+            "Template '{}' not found, expected one of {} or a directory",
+            name,
+            BUILTIN_TEMPLATES.join(", ")
+        )
+        .into())
+    }
+
+    /// Substitutes the variables in the paths and contents of the template's files
+    pub fn render(&self, variables: &HashMap<&str, String>) -> Vec<(String, String)> {
+        self.files
+            .iter()
+            .map(|(path, content)| (substitute(path, variables), substitute(content, variables)))
+            .collect()
+    }
+}
+
+fn read_dir_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(String, String)>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            read_dir_files(root, &path, files)?;
+            continue;
+        }
+
+        // Store the path relative to the template's root, with forward slashes
+        let relative_path = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+
+        files.push((relative_path, content));
+    }
+
+    Ok(())
+}
+
+/// Replaces every `{{variable}}` in the text, leaving unknown variables untouched
+fn substitute(text: &str, variables: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+
+        result.push_str(&rest[..start]);
+
+        match variables.get(after[..end].trim()) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(&rest[start..start + end + 4]),
+        }
+
+        rest = &after[end + 2..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_variables() {
+        let variables = HashMap::from([("name", String::from("game")), ("entry", String::from("main"))]);
+
+        assert_eq!(substitute("{{entry}}.lua", &variables), "main.lua");
+        assert_eq!(substitute("-- {{ name }} v1", &variables), "-- game v1");
+    }
+
+    #[test]
+    fn keeps_unknown_variables() {
+        let variables = HashMap::from([("name", String::from("game"))]);
+
+        assert_eq!(substitute("{{other}} {{name}}", &variables), "{{other}} game");
+        assert_eq!(substitute("unclosed {{name", &variables), "unclosed {{name");
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::parser;
+use crate::path::glob_match;
+use crate::vfs::DiskFs;
+
+/// The extensions of the files that can be modules, as `get_module_path` resolves them
+const MODULE_EXTENSIONS: [&str; 2] = ["lua", "json"];
+
+/// Lists the module files under the source directory that none of the modules resolve to, as
+/// paths relative to the source directory. Files matching one of the ignore globs are left out
+pub fn find_unused(src_dir: &str, modules: &[String], ignore: &[String]) -> io::Result<Vec<String>> {
+    let used: HashSet<String> = modules
+        .iter()
+        .filter_map(|module| parser::module_file(&DiskFs, src_dir, module))
+        .filter_map(|file| {
+            file.strip_prefix(&format!("{}/", src_dir))
+                .map(str::to_string)
+        })
+        .collect();
+
+    let mut files = Vec::new();
+    list_module_files(Path::new(src_dir), "", &mut files)?;
+    files.retain(|file| {
+        !used.contains(file) && !ignore.iter().any(|pattern| glob_match(pattern, file))
+    });
+    files.sort();
+
+    Ok(files)
+}
+
+fn list_module_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = format!("{}{}", prefix, name);
+
+        if entry.file_type()?.is_dir() {
+            list_module_files(&entry.path(), &format!("{}/", relative), files)?;
+            continue;
+        }
+
+        let is_module = Path::new(&name)
+            .extension()
+            .is_some_and(|ext| MODULE_EXTENSIONS.contains(&ext.to_string_lossy().as_ref()));
+
+        if is_module {
+            files.push(relative);
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes a file (relative to the source directory), or moves it to the same place in the
+/// archive directory when one is given
+pub fn remove_file(src_dir: &Path, file: &str, archive: Option<&Path>) -> io::Result<()> {
+    let path = src_dir.join(file);
+
+    match archive {
+        Some(archive) => {
+            let destination = archive.join(file);
+            if destination.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' already exists", destination.display()),
+                ));
+            }
+
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // Renaming fails across file systems, copy the file over then
+            if fs::rename(&path, &destination).is_err() {
+                fs::copy(&path, &destination)?;
+                fs::remove_file(&path)?;
+            }
+        }
+        None => fs::remove_file(&path)?,
+    }
+
+    // Don't leave empty directories behind
+    let mut dir = path.parent();
+    while let Some(cur) = dir {
+        if cur == src_dir || fs::remove_dir(cur).is_err() {
+            break;
+        }
+
+        dir = cur.parent();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BundleOptions, Bundler};
+
+    #[test]
+    fn keeps_the_modules_of_other_profiles() {
+        let dir = std::env::temp_dir().join(format!("luajoin-unused-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.lua"),
+            "--#if ROBLOX\nlocal rbx = require(\"./rbx\")\n--#endif\nreturn 1",
+        )
+        .unwrap();
+        fs::write(dir.join("rbx.lua"), "return 1").unwrap();
+        fs::write(dir.join("old.lua"), "return 1").unwrap();
+
+        let src_dir = dir.to_string_lossy().to_string();
+        let unused = |all_branches: bool| {
+            let mut options = BundleOptions::new(&dir, "main");
+            options.all_branches = all_branches;
+
+            let graph = Bundler::new(options).graph().unwrap();
+            find_unused(&src_dir, &graph.modules, &[]).unwrap()
+        };
+
+        assert_eq!(unused(false), vec!["old.lua", "rbx.lua"]);
+        assert_eq!(unused(true), vec!["old.lua"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Where the bundler resolves and reads modules from
+pub trait FileSystem: Send + Sync {
+    /// Whether there's a file at the path
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+}
+
+/// The real file system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskFs;
+
+impl FileSystem for DiskFs {
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Files kept in memory, like the unsaved buffers of an editor. There are no directories, a file
+/// is only found by its exact path, `.` components aside
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing the one at the same path
+    pub fn insert(&mut self, path: impl AsRef<Path>, source: impl Into<String>) {
+        self.files.insert(normalize(path.as_ref()), source.into());
+    }
+
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<String> {
+        self.files.remove(&normalize(path.as_ref()))
+    }
+}
+
+impl<P: AsRef<Path>, S: Into<String>> FromIterator<(P, S)> for MemoryFs {
+    fn from_iter<I: IntoIterator<Item = (P, S)>>(iter: I) -> Self {
+        let mut memory_fs = Self::new();
+        for (path, source) in iter {
+            memory_fs.insert(path, source);
+        }
+
+        memory_fs
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.files.get(&normalize(path)).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not in memory", path.display()),
+            )
+        })
+    }
+}
+
+/// Drops the `.` components, so `./src/main.lua` and `src/main.lua` are the same file
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BundleOptions, Bundler};
+
+    #[test]
+    fn bundles_from_memory() {
+        let memory_fs: MemoryFs = [
+            ("./src/main.lua", "return require(\"./util\")"),
+            ("src/util/init.lua", "return require(\"./data\")"),
+            ("src/util/data.json", "[1]"),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut bundler = Bundler::new(BundleOptions::new("src", "main")).with_fs(memory_fs);
+        let output = bundler.bundle().unwrap();
+
+        assert_eq!(output.modules, vec!["main", "util", "util/data"]);
+        assert!(output.code.contains("__LUAJOIN_DIRECTORIES[\"util\"]=true"));
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache::CACHE_DIR_NAME;
+use crate::config::Config;
+use crate::path::glob_match;
+
+/// Editor swap and backup files, and version control directories
+pub const DEFAULT_IGNORE: [&str; 6] = ["*.swp", "*.swx", "*~", ".#*", "4913", ".git/"];
+
+/// What a changed path means for the modules
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Change {
+    /// The file of a module, which may have been created, edited or deleted
+    Module(String),
+    /// A directory, or something that was deleted and could have been one. Every module under it
+    /// may have changed
+    Directory(String),
+}
+
+/// Maps the paths reported by a file watcher to the modules of a source directory, whether the
+/// paths go through symlinks or not, and whether the files still exist or not
+pub struct PathMapper {
+    src_dir: PathBuf,
+    canonical_src_dir: PathBuf,
+}
+
+impl PathMapper {
+    pub fn new(src_dir: &Path) -> Self {
+        Self {
+            src_dir: src_dir.to_path_buf(),
+            canonical_src_dir: canonical_dir(src_dir),
+        }
+    }
+
+    /// The directory to watch, with the symlinks resolved
+    pub fn watch_root(&self) -> &Path {
+        &self.canonical_src_dir
+    }
+
+    /// The change a path is part of. Files that can't be modules are ignored, and paths that
+    /// aren't valid UTF-8 are an error as module names are strings
+    pub fn map(&self, path: &Path) -> Result<Option<Change>, String> {
+        let relative = match self.relative(path) {
+            Some(relative) => relative,
+            None => return Ok(None),
+        };
+
+        let relative = relative
+            .to_str()
+            .ok_or_else(|| format!("'{}' is not valid UTF-8, it can't be a module", path.display()))?
+            .replace('\\', "/");
+
+        if relative.is_empty() {
+            return Ok(Some(Change::Directory(relative)));
+        }
+
+        if let Some(module) = module_id(&relative) {
+            return Ok(Some(Change::Module(module)));
+        }
+
+        // What's left is either a directory, or a file that isn't a module
+        if path.is_dir() || !path.exists() {
+            return Ok(Some(Change::Directory(relative)));
+        }
+
+        Ok(None)
+    }
+
+    /// The path relative to the source directory. The parent is resolved rather than the path, as
+    /// the path itself may be a deleted file or a symlink
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let canonical = canonical(path);
+
+        let candidates = canonical.iter().map(PathBuf::as_path).chain([path]);
+        for candidate in candidates {
+            for root in [&self.canonical_src_dir, &self.src_dir] {
+                if let Ok(relative) = candidate.strip_prefix(root) {
+                    return Some(relative.to_path_buf());
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The paths whose changes never rebuild the bundle: the default ones, the project's `watch.ignore`
+/// patterns, and what luajoin writes itself
+pub struct IgnoreFilter {
+    root: PathBuf, // the project's root, with the symlinks resolved
+    patterns: Vec<String>,
+}
+
+impl IgnoreFilter {
+    pub fn new(config: &Config) -> Self {
+        let mut patterns: Vec<String> = DEFAULT_IGNORE.iter().map(|pattern| pattern.to_string()).collect();
+        patterns.extend(config.watch.ignore.iter().cloned());
+        patterns.push(format!("/{}/", config.settings.out_dir.trim_end_matches('/')));
+        patterns.push(format!("/{}/", CACHE_DIR_NAME));
+
+        Self {
+            root: canonical_dir(&config.root),
+            patterns,
+        }
+    }
+
+    /// Whether a changed path is ignored. Paths outside of the project are matched by name only
+    pub fn ignores(&self, path: &Path) -> bool {
+        let path = canonical(path).unwrap_or_else(|| path.to_path_buf());
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => match path.file_name() {
+                Some(name) => Path::new(name),
+                None => return false,
+            },
+        };
+
+        match relative.to_str() {
+            Some(relative) => is_ignored(&self.patterns, &relative.replace('\\', "/"), path.is_dir()),
+            None => false,
+        }
+    }
+}
+
+/// Whether a path (relative to the project's root) matches one of the patterns. A pattern without
+/// a `/` matches any file or directory name, the others match from the root. A trailing `/` only
+/// matches directories, and whatever is inside of a matched directory is matched too
+pub fn is_ignored(patterns: &[String], relative: &str, is_dir: bool) -> bool {
+    let components: Vec<&str> = relative.split('/').filter(|component| !component.is_empty()).collect();
+
+    patterns.iter().any(|pattern| {
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        // A directory is matched by its own path, or as an ancestor of the path
+        let matches_dir = |i: usize| !dir_only || i + 1 < components.len() || is_dir;
+
+        (0..components.len()).any(|i| {
+            let matched = match anchored {
+                true => glob_match(pattern, &components[..=i].join("/")),
+                false => glob_match(pattern, components[i]),
+            };
+
+            matched && matches_dir(i)
+        })
+    })
+}
+
+/// A path with the symlinks of its directory resolved. The path itself may not exist anymore
+pub fn canonical(path: &Path) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+}
+
+/// A directory with its symlinks resolved, `""` being the current directory
+pub fn canonical_dir(dir: &Path) -> PathBuf {
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// The module a file is, from its path relative to the source directory
+pub fn module_id(relative: &str) -> Option<String> {
+    let module = if let Some(dir) = relative.strip_suffix("/init.lua") {
+        dir
+    } else if let Some(module) = relative.strip_suffix(".lua") {
+        module
+    } else {
+        relative.strip_suffix(".json")?
+    };
+
+    Some(module.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_paths_to_modules() {
+        let dir = std::env::temp_dir().join(format!("luajoin-watch-test-{}", std::process::id()));
+        let src_dir = dir.join("src");
+        fs::create_dir_all(src_dir.join("ui")).unwrap();
+        fs::write(src_dir.join("notes.txt"), "").unwrap();
+
+        let mapper = PathMapper::new(&src_dir);
+        let module = |path: &str| Ok(Some(Change::Module(path.to_string())));
+
+        assert_eq!(mapper.map(&src_dir.join("main.lua")), module("main"));
+        assert_eq!(mapper.map(&src_dir.join("ui/init.lua")), module("ui"));
+        assert_eq!(mapper.map(&src_dir.join("ui/data.json")), module("ui/data"));
+        assert_eq!(mapper.map(&src_dir.join("notes.txt")), Ok(None));
+        assert_eq!(mapper.map(&dir.join("other.lua")), Ok(None));
+        assert_eq!(
+            mapper.map(&src_dir.join("ui")),
+            Ok(Some(Change::Directory(String::from("ui"))))
+        );
+        assert_eq!(
+            mapper.map(&src_dir.join("gone")),
+            Ok(Some(Change::Directory(String::from("gone"))))
+        );
+
+        // Through a symlink to the source directory
+        #[cfg(unix)]
+        {
+            let link = dir.join("link");
+            std::os::unix::fs::symlink(&src_dir, &link).unwrap();
+            assert_eq!(PathMapper::new(&link).map(&src_dir.join("a.lua")), module("a"));
+            assert_eq!(mapper.map(&link.join("ui/b.lua")), module("ui/b"));
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_matching_paths() {
+        let patterns: Vec<String> = ["*.swp", ".git/", "/out/", "vendor/tmp"]
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect();
+
+        assert!(is_ignored(&patterns, "src/.main.lua.swp", false));
+        assert!(is_ignored(&patterns, "src/.git/HEAD", false));
+        assert!(is_ignored(&patterns, ".git", true));
+        assert!(!is_ignored(&patterns, ".git", false));
+        assert!(is_ignored(&patterns, "out/bundle.dev.lua", false));
+        assert!(!is_ignored(&patterns, "src/out/main.lua", false));
+        assert!(is_ignored(&patterns, "vendor/tmp/a.lua", false));
+        assert!(!is_ignored(&patterns, "src/main.lua", false));
+    }
+}